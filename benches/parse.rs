@@ -0,0 +1,57 @@
+//! Parsing benchmarks against a real map.
+//!
+//! The map is taken from the BSP_BENCH_MAP environment variable so it doesn't have to live in
+//! the repo. Without it no benchmarks run.
+//!
+//! Usage: BSP_BENCH_MAP=path/to/map.bsp cargo bench
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use source_bsp::lumps::{Lump, LumpParser, LumpType};
+use source_bsp::BspParser;
+
+/// Clears every directory entry except one, so only that lump gets parsed
+fn only_lump(lumps: &[Lump], lump_type: LumpType) -> Vec<Lump> {
+    lumps
+        .iter()
+        .enumerate()
+        .map(|(i, lump)| Lump {
+            fileofs: if i == lump_type as usize {
+                lump.fileofs
+            } else {
+                0
+            },
+            filelen: lump.filelen,
+            version: lump.version,
+            ident: lump.ident,
+        })
+        .collect()
+}
+
+fn parse_benchmarks(c: &mut Criterion) {
+    let path = match std::env::var("BSP_BENCH_MAP") {
+        Ok(path) => path,
+        Err(_) => {
+            eprintln!("BSP_BENCH_MAP isn't set, skipping the parsing benchmarks");
+            return;
+        }
+    };
+
+    let parser = BspParser::new(&path).expect("failed to read BSP_BENCH_MAP");
+    let lumps = parser
+        .read_lump_info()
+        .expect("BSP_BENCH_MAP has a truncated lump directory");
+
+    c.bench_function("parse_lump_data", |b| {
+        b.iter(|| LumpParser::parse_lump_data(lumps.clone(), &parser.data))
+    });
+
+    for lump_type in [LumpType::Faces, LumpType::Vertexes, LumpType::Lighting] {
+        let lumps = only_lump(&lumps, lump_type);
+        c.bench_function(&format!("parse {}", lump_type.name()), |b| {
+            b.iter(|| LumpParser::parse_lump_data(lumps.clone(), &parser.data))
+        });
+    }
+}
+
+criterion_group!(benches, parse_benchmarks);
+criterion_main!(benches);
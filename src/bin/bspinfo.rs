@@ -0,0 +1,116 @@
+//! Prints a JSON summary of a map: its header, lump directory, entities by classname and materials.
+//!
+//! Usage: bspinfo <map.bsp>
+
+use source_bsp::lumps::{LumpParser, LumpType};
+use source_bsp::BspParser;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::process;
+
+/// Quotes a string for JSON, escaping anything that can't appear in it raw
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: bspinfo <map.bsp>");
+            process::exit(1);
+        }
+    };
+
+    let parser = match BspParser::new(&path) {
+        Ok(parser) => parser,
+        Err(e) => {
+            eprintln!("failed to read {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let header = match parser.fetch_header() {
+        Ok(header) => header,
+        Err(e) => {
+            eprintln!("failed to read {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+    let lumps = match parser.read_lump_info() {
+        Ok(lumps) => lumps,
+        Err(e) => {
+            eprintln!("failed to read {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let mut directory = vec![];
+    for (i, lump) in lumps.iter().enumerate() {
+        directory.push(format!(
+            "    {{ \"index\": {}, \"name\": {}, \"offset\": {}, \"length\": {}, \"version\": {}, \"compressed\": {} }}",
+            i,
+            json_string(LumpType::from_index(i).map_or("", |lump_type| lump_type.name())),
+            lump.fileofs,
+            lump.filelen,
+            lump.version,
+            lump.is_compressed()
+        ));
+    }
+
+    let parsed = LumpParser::parse_lump_data(lumps, &parser.data);
+
+    let mut classnames = BTreeMap::new();
+    for entity in &parsed.entities {
+        let classname = entity.get("classname").map_or("", |s| s.as_str());
+        *classnames.entry(classname).or_insert(0) += 1;
+    }
+
+    let materials: BTreeSet<&str> = parsed
+        .texdata
+        .iter()
+        .filter_map(|texdata| parsed.texture_name(texdata))
+        .collect();
+
+    let ident = header.ident.to_le_bytes();
+
+    println!("{{");
+    println!("  \"path\": {},", json_string(&path));
+    println!(
+        "  \"header\": {{ \"ident\": {}, \"version\": {} }},",
+        json_string(&String::from_utf8_lossy(&ident)),
+        header.version
+    );
+    println!("  \"lumps\": [\n{}\n  ],", directory.join(",\n"));
+    println!("  \"entity_count\": {},", parsed.entities.len());
+    println!(
+        "  \"entities\": {{\n{}\n  }},",
+        classnames
+            .iter()
+            .map(|(classname, count)| format!("    {}: {}", json_string(classname), count))
+            .collect::<Vec<_>>()
+            .join(",\n")
+    );
+    println!(
+        "  \"materials\": [\n{}\n  ]",
+        materials
+            .iter()
+            .map(|material| format!("    {}", json_string(material)))
+            .collect::<Vec<_>>()
+            .join(",\n")
+    );
+    println!("}}");
+}
@@ -0,0 +1,119 @@
+use std::string::FromUtf8Error;
+
+use thiserror::Error;
+
+/// Errors that can occur while reading a BSP file
+#[derive(Debug, Error)]
+pub enum BspError {
+    /// The file couldn't be read
+    #[error("failed to read file: {0}")]
+    Io(#[from] std::io::Error),
+    /// A compressed lump couldn't be decompressed
+    #[error("failed to decompress lump: {0}")]
+    Decompression(String),
+    /// The file is too short to hold a BSP header
+    #[error("file is too short for a BSP header: needed {needed} bytes, got {got}")]
+    TruncatedHeader { needed: usize, got: usize },
+    /// The file doesn't start with the VBSP magic
+    #[error("not a VBSP file: found magic {:?}", String::from_utf8_lossy(.found))]
+    BadMagic { found: [u8; 4] },
+    /// The file is a map of another engine or format that this crate doesn't read
+    #[error("{detected} maps aren't supported, only Source VBSP")]
+    UnsupportedFormat { detected: &'static str },
+    /// The header's BSP version isn't one used by any Source engine game
+    #[error("unsupported VBSP version {0}")]
+    UnsupportedVersion(i32),
+    /// A lump directory entry points outside of the file
+    #[error("lump {lump} at offset {offset} with length {length} is outside of the {file_len} byte file")]
+    LumpOutOfBounds {
+        lump: usize,
+        offset: i32,
+        length: i32,
+        file_len: usize,
+    },
+    /// A read ran past the end of the data
+    #[error("read of {needed} bytes at offset {offset} with only {available} available")]
+    ShortRead {
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    /// A float in a lump is NaN or infinite, with ParseOptions::reject_nonfinite_floats on
+    #[error("lump {lump} has a NaN or infinite float at offset {offset}")]
+    NonFiniteFloat { lump: usize, offset: usize },
+    /// A range of one lump referenced by another runs outside of it
+    #[error("range of {count} items from {first} is outside of the {len} available")]
+    SliceOutOfRange { first: i64, count: i64, len: usize },
+    /// A lump has more elements than ParseLimits::max_lump_elements allows
+    #[error("lump {lump} has more than the {limit} elements allowed")]
+    TooManyElements { lump: usize, limit: usize },
+    /// A compressed block would decompress to more than ParseLimits::max_decompressed_size
+    #[error("compressed data decompresses to {size} bytes, more than the {limit} allowed")]
+    DecompressedTooLarge { size: usize, limit: usize },
+    /// Walking the BSP tree went deeper than the traversal depth limit
+    #[error("BSP tree is deeper than the traversal limit of {limit}")]
+    TraversalTooDeep { limit: usize },
+    /// Walking the BSP tree reached a node that was already on the path to it
+    #[error("BSP tree loops back to node {node}")]
+    NodeCycle { node: u32 },
+    /// A string wasn't valid UTF-8
+    #[error("invalid UTF-8: {0}")]
+    Utf8(#[from] FromUtf8Error),
+}
+
+// io::Error isn't Clone, so it's rebuilt from its kind and message
+impl Clone for BspError {
+    fn clone(&self) -> Self {
+        match self {
+            BspError::Io(e) => BspError::Io(std::io::Error::new(e.kind(), e.to_string())),
+            BspError::Decompression(message) => BspError::Decompression(message.clone()),
+            BspError::TruncatedHeader { needed, got } => BspError::TruncatedHeader {
+                needed: *needed,
+                got: *got,
+            },
+            BspError::BadMagic { found } => BspError::BadMagic { found: *found },
+            BspError::UnsupportedFormat { detected } => BspError::UnsupportedFormat { detected },
+            BspError::UnsupportedVersion(version) => BspError::UnsupportedVersion(*version),
+            BspError::LumpOutOfBounds {
+                lump,
+                offset,
+                length,
+                file_len,
+            } => BspError::LumpOutOfBounds {
+                lump: *lump,
+                offset: *offset,
+                length: *length,
+                file_len: *file_len,
+            },
+            BspError::ShortRead {
+                offset,
+                needed,
+                available,
+            } => BspError::ShortRead {
+                offset: *offset,
+                needed: *needed,
+                available: *available,
+            },
+            BspError::NonFiniteFloat { lump, offset } => BspError::NonFiniteFloat {
+                lump: *lump,
+                offset: *offset,
+            },
+            BspError::SliceOutOfRange { first, count, len } => BspError::SliceOutOfRange {
+                first: *first,
+                count: *count,
+                len: *len,
+            },
+            BspError::TooManyElements { lump, limit } => BspError::TooManyElements {
+                lump: *lump,
+                limit: *limit,
+            },
+            BspError::DecompressedTooLarge { size, limit } => BspError::DecompressedTooLarge {
+                size: *size,
+                limit: *limit,
+            },
+            BspError::TraversalTooDeep { limit } => BspError::TraversalTooDeep { limit: *limit },
+            BspError::NodeCycle { node } => BspError::NodeCycle { node: *node },
+            BspError::Utf8(e) => BspError::Utf8(e.clone()),
+        }
+    }
+}
@@ -0,0 +1,3 @@
+//! Writes map geometry out in formats other tools can read
+
+pub mod obj;
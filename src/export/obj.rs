@@ -0,0 +1,252 @@
+//! Wavefront OBJ export of the world's brush geometry
+
+use crate::lumps::lump_types::Face;
+use crate::lumps::LumpParser::{ParsedLumps, TOOL_SURFACES};
+
+use std::io::{self, Write};
+
+/// Writes the faces of the world, model 0, as a Wavefront OBJ mesh.
+///
+/// Every vertex of the map is written in `vertex_list` order, in Source's Z-up units, so
+/// faces share corners the way the map does. Faces are grouped by material with `usemtl`,
+/// and since they're convex each one is written as a fan of triangles. Like
+/// build_index_buffer, displacement base faces and tool surfaces such as nodraw and
+/// skybox faces are left out.
+///
+/// The displacements follow as their own grids of vertices. Each vertex carries its blend
+/// alpha as a grey vertex color, `v x y z a a a`, which most OBJ importers read, so the
+/// blend between a blend material's two textures can be rebuilt.
+pub fn write_obj<W: Write>(lumps: &ParsedLumps, out: &mut W) -> io::Result<()> {
+    for vertex in &lumps.vertex_list {
+        writeln!(out, "v {} {} {}", vertex.x, vertex.y, vertex.z)?;
+    }
+
+    let mut faces: Vec<(&Face, Option<&str>)> = lumps
+        .model_faces(0)
+        .iter()
+        .filter(|face| face.displacement_info < 0)
+        .filter(|face| !lumps.face_surface_flags(face).intersects(TOOL_SURFACES))
+        .map(|face| (face, lumps.face_texture_name(face)))
+        .collect();
+    faces.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let mut current_material = None;
+    for (face, material) in faces {
+        use_material(out, &mut current_material, material)?;
+
+        // OBJ indices count from 1. Corners outside of the vertex list are dropped.
+        let corners: Vec<usize> = lumps
+            .face_vertex_indices(face)
+            .into_iter()
+            .filter(|&index| index < lumps.vertex_list.len())
+            .map(|index| index + 1)
+            .collect();
+
+        for i in 1..corners.len().saturating_sub(1) {
+            writeln!(out, "f {} {} {}", corners[0], corners[i], corners[i + 1])?;
+        }
+    }
+
+    let mut first_vertex = lumps.vertex_list.len() + 1;
+    for disp in &lumps.displacement_info {
+        let mesh = match lumps.build_displacement(disp) {
+            Some(mesh) => mesh,
+            None => continue,
+        };
+        let material = lumps
+            .displacement_base_face(disp)
+            .and_then(|face| lumps.face_texture_name(face));
+        use_material(out, &mut current_material, material)?;
+
+        for (position, alpha) in mesh.positions.iter().zip(&mesh.alphas) {
+            writeln!(
+                out,
+                "v {} {} {} {} {} {}",
+                position.0, position.1, position.2, alpha, alpha, alpha
+            )?;
+        }
+        for [a, b, c] in mesh.triangles() {
+            let index = |i: u32| first_vertex + i as usize;
+            writeln!(out, "f {} {} {}", index(a), index(b), index(c))?;
+        }
+
+        first_vertex += mesh.positions.len();
+    }
+
+    Ok(())
+}
+
+/// Switches to a face's material if it has one and it isn't the current one
+fn use_material<'a, W: Write>(
+    out: &mut W,
+    current: &mut Option<&'a str>,
+    material: Option<&'a str>,
+) -> io::Result<()> {
+    match material {
+        Some(name) if material != *current => {
+            // OBJ names end at whitespace
+            writeln!(out, "usemtl {}", name.replace(' ', "_"))?;
+            *current = material;
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+#[test]
+fn test_write_obj() {
+    use crate::lumps::lump_types::*;
+
+    let face = |first_edge, texinfo| Face {
+        texinfo,
+        ..crate::fixture::face(first_edge, 4)
+    };
+    let vertex = |x, y| Vertex { x, y, z: 0.0 };
+    let edge = |a, b| Edge {
+        vertex_indicies: [a, b],
+    };
+
+    // Two quads sharing the edge from vertex 1 to 2, only the second one with a material
+    let lumps = ParsedLumps {
+        vertex_list: vec![
+            vertex(0.0, 0.0),
+            vertex(1.0, 0.0),
+            vertex(1.0, 1.0),
+            vertex(0.0, 1.0),
+            vertex(2.0, 0.0),
+            vertex(2.0, 1.0),
+        ],
+        edges: vec![
+            edge(0, 0),
+            edge(0, 1),
+            edge(1, 2),
+            edge(2, 3),
+            edge(3, 0),
+            edge(1, 4),
+            edge(4, 5),
+            edge(5, 2),
+        ],
+        surfedges: vec![1, 2, 3, 4, 5, 6, 7, -2],
+        faces: vec![face(4, 0), face(0, -1)],
+        texinfo: vec![TexInfo {
+            texture_vecs: [[0.0; 4]; 2],
+            lightmap_vecs: [[0.0; 4]; 2],
+            flags: 0,
+            tex_data: 0,
+        }],
+        texdata: vec![TexData {
+            reflectivity: (0.0, 0.0, 0.0),
+            texdata_string_table_index: 0,
+            width: 64,
+            height: 64,
+            view_width: 64,
+            view_height: 64,
+        }],
+        texdata_string_table: vec![0],
+        texdata_string_data: b"BRICK/WALL01\0".to_vec(),
+        models: vec![Model {
+            mins: (0.0, 0.0, 0.0),
+            maxs: (2.0, 1.0, 0.0),
+            origin: (0.0, 0.0, 0.0),
+            head_node: 0,
+            first_face: 0,
+            num_faces: 2,
+        }],
+        ..Default::default()
+    };
+
+    let mut out = vec![];
+    write_obj(&lumps, &mut out).unwrap();
+    let obj = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = obj.lines().collect();
+
+    assert_eq!(
+        lines.iter().filter(|line| line.starts_with("v ")).count(),
+        6
+    );
+    assert_eq!(lines[1], "v 1 0 0");
+    assert_eq!(
+        lines[6..],
+        [
+            "f 1 2 3",
+            "f 1 3 4",
+            "usemtl BRICK/WALL01",
+            "f 2 5 6",
+            "f 2 6 3"
+        ]
+    );
+}
+
+#[test]
+fn test_write_obj_displacement_alpha() {
+    use crate::lumps::lump_types::*;
+    use crate::lumps::LumpReader;
+
+    let vertex = |x, y| Vertex { x, y, z: 0.0 };
+    let edge = |a, b| Edge {
+        vertex_indicies: [a, b],
+    };
+    let mut disp = DisplacementInfo::from_reader(&mut LumpReader::new(&[0; 176]));
+    disp.power = 1;
+
+    // A flat 3x3 displacement whose alpha goes from 0 at the first vertex to 255 at the last
+    let lumps = ParsedLumps {
+        vertex_list: vec![
+            vertex(0.0, 0.0),
+            vertex(4.0, 0.0),
+            vertex(4.0, 4.0),
+            vertex(0.0, 4.0),
+        ],
+        edges: vec![edge(0, 1), edge(1, 2), edge(2, 3), edge(3, 0)],
+        surfedges: vec![0, 1, 2, 3],
+        faces: vec![Face {
+            displacement_info: 0,
+            ..crate::fixture::face(0, 4)
+        }],
+        displacement_info: vec![disp],
+        disp_verts: (0..9)
+            .map(|i| DispVert {
+                vec: (0.0, 0.0, 1.0),
+                dist: 0.0,
+                alpha: i as f32 * 255.0 / 8.0,
+            })
+            .collect(),
+        models: vec![Model {
+            mins: (0.0, 0.0, 0.0),
+            maxs: (4.0, 4.0, 0.0),
+            origin: (0.0, 0.0, 0.0),
+            head_node: 0,
+            first_face: 0,
+            num_faces: 1,
+        }],
+        ..Default::default()
+    };
+
+    let mut out = vec![];
+    write_obj(&lumps, &mut out).unwrap();
+    let obj = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = obj.lines().collect();
+
+    // The base face isn't written, only its displacement
+    let colored: Vec<&str> = lines
+        .iter()
+        .filter(|line| line.starts_with("v ") && line.split(' ').count() == 7)
+        .copied()
+        .collect();
+    assert_eq!(colored.len(), 9);
+    assert_eq!(colored[0], "v 0 0 0 0 0 0");
+    assert_eq!(colored[4], "v 2 2 0 0.5 0.5 0.5");
+    assert_eq!(colored[8], "v 4 4 0 1 1 1");
+
+    let faces: Vec<&str> = lines
+        .iter()
+        .filter(|line| line.starts_with("f "))
+        .copied()
+        .collect();
+    assert_eq!(faces.len(), 8);
+    // Displacement vertices are numbered after the map's 4
+    assert!(faces.iter().all(|face| face
+        .split(' ')
+        .skip(1)
+        .all(|i| (5..=13).contains(&i.parse::<usize>().unwrap()))));
+}
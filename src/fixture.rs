@@ -0,0 +1,158 @@
+//! Builds small maps in memory so tests don't depend on files outside of the repo
+
+use crate::lumps::lump_types::{ContentsFlags, Face, Leaf, Node, Vector};
+use crate::lumps::{Endianness, LumpType};
+use crate::HEADER_SIZE;
+
+/// Lays out a VBSP file from the lumps it's given, in the order they're added
+pub(crate) struct FixtureBuilder {
+    version: i32,
+    map_revision: i32,
+    endianness: Endianness,
+    /// Each lump with its version
+    lumps: Vec<(LumpType, i32, Vec<u8>)>,
+}
+
+impl FixtureBuilder {
+    pub fn new() -> Self {
+        FixtureBuilder {
+            version: 20,
+            map_revision: 1,
+            endianness: Endianness::Little,
+            lumps: vec![],
+        }
+    }
+
+    /// Sets the map version in the header
+    pub fn version(mut self, version: i32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Writes the header, directory and vertices big-endian like a console map.
+    /// Lumps added with `lump` are written as they're given.
+    pub fn big_endian(mut self) -> Self {
+        self.endianness = Endianness::Big;
+        self
+    }
+
+    pub fn lump(self, lump_type: LumpType, data: Vec<u8>) -> Self {
+        self.versioned_lump(lump_type, 0, data)
+    }
+
+    pub fn versioned_lump(mut self, lump_type: LumpType, version: i32, data: Vec<u8>) -> Self {
+        self.lumps.push((lump_type, version, data));
+        self
+    }
+
+    /// Adds the entities lump, NUL terminated like the compiler writes it
+    pub fn entities(self, text: &str) -> Self {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        self.lump(LumpType::Entities, data)
+    }
+
+    pub fn vertices(self, vertices: &[Vector]) -> Self {
+        let mut data = vec![];
+        for vertex in vertices {
+            for f in [vertex.0, vertex.1, vertex.2] {
+                data.extend_from_slice(&match self.endianness {
+                    Endianness::Little => f.to_le_bytes(),
+                    Endianness::Big => f.to_be_bytes(),
+                });
+            }
+        }
+        self.lump(LumpType::Vertexes, data)
+    }
+
+    pub fn build(self) -> Vec<u8> {
+        let mut directory = [(0i32, 0i32, 0i32); 64];
+        let mut body = vec![];
+
+        for (lump_type, version, data) in &self.lumps {
+            // Lumps are 4 byte aligned
+            while body.len() % 4 != 0 {
+                body.push(0);
+            }
+            directory[*lump_type as usize] = (
+                (HEADER_SIZE + body.len()) as i32,
+                data.len() as i32,
+                *version,
+            );
+            body.extend_from_slice(data);
+        }
+
+        let int = |value: i32| match self.endianness {
+            Endianness::Little => value.to_le_bytes(),
+            Endianness::Big => value.to_be_bytes(),
+        };
+
+        let mut out = int(crate::VBSP_MAGIC).to_vec();
+        out.extend_from_slice(&int(self.version));
+        for (fileofs, filelen, version) in &directory {
+            out.extend_from_slice(&int(*fileofs));
+            out.extend_from_slice(&int(*filelen));
+            out.extend_from_slice(&int(*version));
+            out.extend_from_slice(&[0; 4]); // ident
+        }
+        out.extend_from_slice(&int(self.map_revision));
+        out.extend_from_slice(&body);
+
+        out
+    }
+}
+
+/// A world face using num_edges surfedges from first_edge, on plane 0 with no texinfo,
+/// displacement or lightmap. Tests set whatever else they check with struct update syntax.
+pub(crate) fn face(first_edge: i32, num_edges: i16) -> Face {
+    Face {
+        plane_num: 0,
+        side: 0,
+        on_node: 0,
+        first_edge,
+        num_edges,
+        texinfo: -1,
+        displacement_info: -1,
+        surface_fog_volume_id: -1,
+        styles: [255; 4],
+        light_offset: -1,
+        area: 0.0,
+        lightmap_texture_mins_in_luxels: [0, 0],
+        lightmap_texture_size_in_luxels: [0, 0],
+        original_face: -1,
+        num_primitives: 0,
+        first_primitave_id: 0,
+        smoothing_groups: 0,
+    }
+}
+
+/// A leaf with the given contents, outside of any cluster and with no faces or brushes
+pub(crate) fn leaf(contents: ContentsFlags) -> Leaf {
+    Leaf {
+        contents: contents.0,
+        cluster: -1,
+        area_flags: 0,
+        mins: [0; 3],
+        maxs: [0; 3],
+        first_leaf_face: 0,
+        num_leaf_faces: 0,
+        first_leaf_brush: 0,
+        num_leaf_brushes: 0,
+        leaf_water_data_id: -1,
+        ambient_lighting: None,
+    }
+}
+
+/// A node splitting on plane 0 into the given children, with no faces
+pub(crate) fn node(children: [i32; 2]) -> Node {
+    Node {
+        plane_num: 0,
+        children,
+        mins: [0; 3],
+        maxs: [0; 3],
+        first_face: 0,
+        num_faces: 0,
+        area: 0,
+        padding: 0,
+    }
+}
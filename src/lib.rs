@@ -1,82 +1,781 @@
-#[macro_use]
-extern crate lazy_static;
+//! Reads Source engine (VBSP) maps.
+//!
+//! [`parse_file`] and [`parse_bytes`] are the simplest way in: they check the header, read the
+//! lump directory and parse every lump, returning an error rather than panicking on a bad file.
+//! A lump that fails to parse doesn't fail the map, it's left empty and recorded in
+//! `lump_errors`. [`BspFile`] keeps the header alongside the lumps, and [`BspParser`] reads
+//! individual lumps without parsing the rest.
+//!
+//! ```no_run
+//! let lumps = source_bsp::parse_file("maps/example.bsp")?;
+//! println!("{} entities", lumps.entities.len());
+//! # Ok::<(), source_bsp::error::BspError>(())
+//! ```
 
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 
+use std::borrow::Cow;
 use std::convert::TryInto;
 
-mod lumps;
+pub mod error;
+pub mod export;
+#[cfg(test)]
+mod fixture;
+pub mod lumps;
+pub mod writer;
+use error::BspError;
+use lumps::lump_types::Entity;
 use lumps::*;
 
-#[derive(Debug)]
-struct BspHeader {
-    ident: i32,
-    version: i32,
+/// "VBSP" read as a little-endian int, the ident every Source map starts with.
+/// Big-endian console maps store the same int, so their files start with "PSBV".
+pub const VBSP_MAGIC: i32 = 0x50534256;
+
+/// Magics of other map formats, so they can be reported as unsupported rather than corrupt
+const OTHER_FORMATS: [([u8; 4], &str); 4] = [
+    (*b"rBSP", "Respawn (Titanfall/Apex Legends)"),
+    (*b"IBSP", "Quake 2/3"),
+    ([0x34, 0x12, 0xaa, 0x55], "Source 2 VPK"),
+    ([30, 0, 0, 0], "GoldSrc"),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BspHeader {
+    pub ident: i32,
+    pub version: i32,
+    /// Incremented each time the map is compiled, 0 if the file is too short to have one
+    pub map_revision: i32,
+}
+
+/// VBSP versions used by Source engine games and their branches, starting at Vampire: Bloodlines (17)
+const SUPPORTED_VERSIONS: std::ops::RangeInclusive<i32> = 17..=29;
+
+/// Number of entries in the lump directory, one for each LumpType
+pub const LUMP_COUNT: usize = 64;
+
+/// Size of the header, lump directory and map revision at the start of the file
+pub const HEADER_SIZE: usize = 8 + LUMP_COUNT * 16 + 4;
+
+/// Reads and parses every lump of a map
+pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<LumpParser::ParsedLumps, BspError> {
+    Ok(BspFile::open(path)?.lumps)
+}
+
+/// Parses every lump of a map already in memory, reading straight from `data`
+pub fn parse_bytes(data: &[u8]) -> Result<LumpParser::ParsedLumps, BspError> {
+    read_header(data)?;
+    Ok(LumpParser::parse_lump_data(read_directory(data)?, data))
+}
+
+/// A map's header along with all of its parsed lumps
+#[derive(Debug, Clone)]
+pub struct BspFile {
+    pub header: BspHeader,
+    pub lumps: LumpParser::ParsedLumps,
+}
+
+impl BspFile {
+    /// Reads and parses a map
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, BspError> {
+        Self::from_parser(&BspParser::new(path)?)
+    }
+
+    /// Parses a map already in memory
+    pub fn from_bytes(data: &[u8]) -> Result<Self, BspError> {
+        Ok(BspFile {
+            header: read_header(data)?,
+            lumps: LumpParser::parse_lump_data(read_directory(data)?, data),
+        })
+    }
+
+    fn from_parser(parser: &BspParser) -> Result<Self, BspError> {
+        Self::from_bytes(&parser.data)
+    }
+
+    /// Lazily opens every .bsp file in a directory, not including subdirectories.
+    /// Each map gets its own result, so one bad map doesn't stop the rest.
+    pub fn open_dir<P: AsRef<Path>>(
+        path: P,
+    ) -> impl Iterator<Item = (PathBuf, Result<BspFile, BspError>)> {
+        let dir = path.as_ref().to_path_buf();
+
+        let (entries, error) = match std::fs::read_dir(&dir) {
+            Ok(entries) => (Some(entries), None),
+            Err(e) => (None, Some((dir.clone(), Err(e.into())))),
+        };
+
+        let maps = entries.into_iter().flatten().filter_map(move |entry| {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => return Some((dir.clone(), Err(e.into()))),
+            };
+
+            let is_bsp = path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("bsp"));
+            if !is_bsp || !path.is_file() {
+                return None;
+            }
+
+            let map = BspFile::open(&path);
+            Some((path, map))
+        });
+
+        error.into_iter().chain(maps)
+    }
+}
+
+/// The bytes of a map, either read into memory or mapped from the file
+pub enum MapData {
+    Owned(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
+impl std::ops::Deref for MapData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            MapData::Owned(data) => data,
+            #[cfg(feature = "mmap")]
+            MapData::Mapped(map) => map,
+        }
+    }
+}
+
+impl AsRef<[u8]> for MapData {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
 }
 
-struct BspParser {
-    pub data: Vec<u8>,
+impl From<Vec<u8>> for MapData {
+    fn from(data: Vec<u8>) -> Self {
+        MapData::Owned(data)
+    }
+}
+
+pub struct BspParser {
+    pub data: MapData,
 }
 
 impl BspParser {
-    pub fn new(path: &str) -> Result<Self, std::io::Error> {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, BspError> {
         let mut file = File::open(path)?;
         let mut contents = vec![];
 
         file.read_to_end(&mut contents)?;
 
-        Ok(BspParser { data: contents })
+        #[cfg(feature = "compressed-input")]
+        let contents = decompress_input(contents)?;
+
+        Ok(BspParser {
+            data: contents.into(),
+        })
+    }
+
+    /// Maps the file into memory rather than reading it, so only the parts of the map that
+    /// are used get loaded. The file shouldn't be modified while the parser is alive.
+    /// Compressed input isn't decompressed, the file has to be a plain .bsp.
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        // Safe as long as nothing truncates the file while it's mapped
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+
+        Ok(BspParser {
+            data: MapData::Mapped(map),
+        })
+    }
+
+    /// Reads the header, checking that the file is a Source engine map.
+    ///
+    /// Fails with TruncatedHeader if the data is too short for the magic and version,
+    /// UnsupportedFormat or BadMagic if it isn't a VBSP file, and UnsupportedVersion for
+    /// versions no Source game uses.
+    pub fn fetch_header(&self) -> Result<BspHeader, BspError> {
+        read_header(&self.data)
+    }
+
+    /// The byte order of the map, going by its magic. Little-endian if it isn't a VBSP file.
+    pub fn endianness(&self) -> Endianness {
+        Endianness::of_map(&self.data).unwrap_or_default()
+    }
+
+    /// Reads only the header and lump directory of a map, without loading the rest of the file
+    pub fn read_header_only<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<(BspHeader, [Lump; LUMP_COUNT]), BspError> {
+        let mut data = vec![];
+        File::open(path)?
+            .take(HEADER_SIZE as u64)
+            .read_to_end(&mut data)?;
+
+        let parser = BspParser { data: data.into() };
+        let header = parser.fetch_header()?;
+
+        if parser.data.len() < HEADER_SIZE {
+            return Err(BspError::TruncatedHeader {
+                needed: HEADER_SIZE,
+                got: parser.data.len(),
+            });
+        }
+
+        Ok((header, parser.read_lump_info()?))
+    }
+
+    /// Reads and parses only the entities lump of a map, without loading any geometry
+    pub fn entities_only<P: AsRef<Path>>(path: P) -> Result<Vec<Entity>, BspError> {
+        Self::entities_only_with_limits(path, &LumpParser::ParseLimits::default())
+    }
+
+    /// Like entities_only, with limits.max_decompressed_size capping a compressed lump
+    pub fn entities_only_with_limits<P: AsRef<Path>>(
+        path: P,
+        limits: &LumpParser::ParseLimits,
+    ) -> Result<Vec<Entity>, BspError> {
+        let path = path.as_ref();
+        let (_, lumps) = Self::read_header_only(path)?;
+        let lump = &lumps[LumpType::Entities as usize];
+
+        if lump.fileofs <= 0 || lump.filelen <= 0 {
+            return Ok(vec![]);
+        }
+
+        let mut file = File::open(path)?;
+        // The directory isn't trusted to size the buffer until it's checked against the file
+        let file_len = file.metadata()?.len();
+        if lump.fileofs as u64 + lump.filelen as u64 > file_len {
+            return Err(BspError::LumpOutOfBounds {
+                lump: LumpType::Entities as usize,
+                offset: lump.fileofs,
+                length: lump.filelen,
+                file_len: file_len as usize,
+            });
+        }
+        file.seek(SeekFrom::Start(lump.fileofs as u64))?;
+
+        let mut data = vec![0; lump.filelen as usize];
+        file.read_exact(&mut data)?;
+
+        if lump.is_compressed() {
+            data = LumpParser::decompress_block(&data, limits.max_decompressed_size)?;
+        }
+
+        Ok(entities::parse_entities(&entities::decode_entities_lump(
+            &data,
+        )))
     }
 
-    pub fn fetch_header(&self) -> BspHeader {
-        BspHeader {
-            ident: i32::from_le_bytes(self.data[0..4].try_into().unwrap()),
-            version: i32::from_le_bytes(self.data[4..8].try_into().unwrap()),
+    /// Returns where a lump is in the file as (offset, length),
+    /// or None if the map doesn't include it or it's outside of the file
+    pub fn lump_range(&self, lump_type: LumpType) -> Option<(usize, usize)> {
+        let lump = &self.read_lump_info().ok()?[lump_type as usize];
+        if lump.fileofs == 0 {
+            return None;
         }
+
+        LumpParser::lump_bytes(lump, &self.data)?;
+        Some((lump.fileofs as usize, lump.filelen as usize))
     }
 
-    pub fn read_lump_info(&self) -> Vec<Lump> {
-        let mut lumps = vec![];
-
-        for i in 0..64 {
-            lumps.push(Lump {
-                fileofs: i32::from_le_bytes(
-                    self.data[(8 + (i * 16))..(8 + 4 + (i * 16))]
-                        .try_into()
-                        .unwrap(),
-                ),
-                filelen: i32::from_le_bytes(
-                    self.data[(8 + 4 + (i * 16))..(8 + 4 + 4 + (i * 16))]
-                        .try_into()
-                        .unwrap(),
-                ),
-                version: i32::from_le_bytes(
-                    self.data[(8 + 8 + (i * 16))..(8 + 4 + 8 + (i * 16))]
-                        .try_into()
-                        .unwrap(),
-                ),
-                ident: self.data[(8 + 12 + (i * 16))..(8 + 4 + 12 + (i * 16))]
-                    .try_into()
-                    .unwrap(),
-            })
+    /// Returns the contents of a lump without parsing it, decompressing it if needed.
+    /// None if the map doesn't include it, it's outside of the file or it fails to decompress.
+    pub fn lump_bytes(&self, lump_type: LumpType) -> Option<Cow<'_, [u8]>> {
+        let lump = &self.read_lump_info().ok()?[lump_type as usize];
+        if lump.fileofs == 0 {
+            return None;
+        }
+
+        let data = LumpParser::lump_bytes(lump, &self.data)?;
+        if !lump.is_compressed() {
+            return Some(Cow::Borrowed(data));
         }
 
-        lumps
+        LumpParser::decompress_lump(data).ok().map(Cow::Owned)
+    }
+
+    /// Reads the lump directory, one entry for each LumpType in index order.
+    /// Fails with TruncatedHeader if the data is too short to hold the whole directory.
+    pub fn read_lump_info(&self) -> Result<[Lump; LUMP_COUNT], BspError> {
+        read_directory(&self.data)
     }
 }
 
+/// Reads the header at the start of a map, see BspParser::fetch_header
+fn read_header(data: &[u8]) -> Result<BspHeader, BspError> {
+    if data.len() < 8 {
+        return Err(BspError::TruncatedHeader {
+            needed: 8,
+            got: data.len(),
+        });
+    }
+
+    let magic: [u8; 4] = data[0..4].try_into().unwrap();
+
+    if Endianness::of_map(&magic).is_none() {
+        return Err(
+            match OTHER_FORMATS.iter().find(|(other, _)| *other == magic) {
+                Some((_, detected)) => BspError::UnsupportedFormat { detected },
+                None => BspError::BadMagic { found: magic },
+            },
+        );
+    }
+
+    let mut header = map_reader(data, &data[..8]);
+    let ident = header.read_i32();
+    let version = header.read_i32();
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        return Err(BspError::UnsupportedVersion(version));
+    }
+
+    Ok(BspHeader {
+        ident,
+        version,
+        map_revision: data
+            .get(HEADER_SIZE - 4..HEADER_SIZE)
+            .map_or(0, |bytes| map_reader(data, bytes).read_i32()),
+    })
+}
+
+/// Reads the lump directory of a map, see BspParser::read_lump_info
+fn read_directory(data: &[u8]) -> Result<[Lump; LUMP_COUNT], BspError> {
+    let directory = data
+        .get(8..HEADER_SIZE - 4)
+        .ok_or(BspError::TruncatedHeader {
+            needed: HEADER_SIZE - 4,
+            got: data.len(),
+        })?;
+
+    let big_endian = Endianness::of_map(data).unwrap_or_default() == Endianness::Big;
+
+    Ok(directory
+        .chunks_exact(16)
+        .map(|entry| {
+            let mut entry = map_reader(data, entry);
+            let mut lump = Lump {
+                fileofs: entry.read_i32(),
+                filelen: entry.read_i32(),
+                version: entry.read_i32(),
+                ident: entry.get_data()[12..16].try_into().unwrap(),
+            };
+            // The ident holds the decompressed size, kept little-endian like PC maps
+            if big_endian {
+                lump.ident.reverse();
+            }
+            lump
+        })
+        .collect::<Vec<_>>()
+        .try_into()
+        .expect("the directory is LUMP_COUNT entries long"))
+}
+
+/// A reader over part of a map in its byte order
+fn map_reader<'a>(map: &[u8], data: &'a [u8]) -> LumpReader<'a> {
+    let mut reader = LumpReader::new(data);
+    reader.set_endianness(Endianness::of_map(map).unwrap_or_default());
+    reader
+}
+
+/// Decompresses a gzip or bzip2 wrapped map, passing anything else through unchanged
+#[cfg(feature = "compressed-input")]
+fn decompress_input(data: Vec<u8>) -> Result<Vec<u8>, std::io::Error> {
+    let mut out = vec![];
+
+    if data.starts_with(&[0x1f, 0x8b]) {
+        flate2::read::GzDecoder::new(&data[..]).read_to_end(&mut out)?;
+    } else if data.starts_with(b"BZh") {
+        bzip2::read::BzDecoder::new(&data[..]).read_to_end(&mut out)?;
+    } else {
+        return Ok(data);
+    }
+
+    Ok(out)
+}
+
 #[test]
 fn test_program() {
-    let bsp_parser = BspParser::new("arena_badlands.bsp").unwrap();
+    let bsp_parser = BspParser {
+        data: fixture::FixtureBuilder::new()
+            .entities(concat!(
+                "{\n\"classname\" \"worldspawn\"\n\"detailvbsp\" \"detail.vbsp\"\n}\n",
+                "{\n\"classname\" \"info_player_start\"\n}\n",
+            ))
+            .vertices(&[(0.0, 0.0, 0.0), (64.0, 0.0, 0.0), (64.0, 64.0, 0.0)])
+            .build()
+            .into(),
+    };
+
+    let header = bsp_parser.fetch_header().unwrap();
+    assert_eq!(header.version, 20);
+    assert_eq!(header.map_revision, 1);
+
+    assert_eq!(
+        bsp_parser.lump_range(LumpType::Vertexes),
+        Some((HEADER_SIZE + 96, 36))
+    );
+    assert_eq!(bsp_parser.lump_range(LumpType::Faces), None);
+    assert!(bsp_parser
+        .lump_bytes(LumpType::Entities)
+        .unwrap()
+        .starts_with(b"{\n\"classname\" \"worldspawn\""));
+
+    let mut progress = vec![];
+    let parsed = LumpParser::parse_lump_data_with_progress(
+        bsp_parser.read_lump_info().unwrap(),
+        &bsp_parser.data,
+        &Default::default(),
+        |lump_type, fraction| progress.push((lump_type, fraction)),
+    );
+
+    assert_eq!(progress.len(), 64);
+    assert_eq!(progress[0].0, LumpType::Entities);
+    assert!(progress[0].1 > 0.0 && progress[0].1 < 1.0);
+    assert_eq!(progress[63].1, 1.0);
+
+    assert!(parsed.lump_errors.is_empty());
+    assert_eq!(parsed.entities.len(), 2);
+    assert_eq!(parsed.entities[1]["classname"], "info_player_start");
+    let worldspawn = parsed.worldspawn_info().unwrap();
+    assert_eq!(worldspawn.detail_vbsp.as_deref(), Some("detail.vbsp"));
+    assert_eq!(worldspawn.detail_material, None);
+    assert_eq!(parsed.vertex_list.len(), 3);
+    assert_eq!(parsed.vertex_list[2].y, 64.0);
+}
+
+#[test]
+fn test_header_formats() {
+    let parser = |data: &[u8]| BspParser {
+        data: data.to_vec().into(),
+    };
 
-    println!("{:?}", bsp_parser.fetch_header());
-    println!("{:?}", bsp_parser.read_lump_info());
+    assert!(matches!(
+        parser(b"VBS").fetch_header(),
+        Err(BspError::TruncatedHeader { needed: 8, got: 3 })
+    ));
+    assert!(matches!(
+        parser(b"rBSP\x2f\0\0\0").fetch_header(),
+        Err(BspError::UnsupportedFormat { .. })
+    ));
+    assert!(matches!(
+        parser(b"PK\x03\x04\0\0\0\0").fetch_header(),
+        Err(BspError::BadMagic {
+            found: [b'P', b'K', 3, 4]
+        })
+    ));
+    let header = parser(b"VBSP\x14\0\0\0").fetch_header().unwrap();
+    assert_eq!(header.ident, VBSP_MAGIC);
+    assert_eq!(header.version, 20);
+    // A valid header with the lump directory cut off
+    assert!(matches!(
+        parser(b"VBSP\x14\0\0\0").read_lump_info(),
+        Err(BspError::TruncatedHeader {
+            needed: 1032,
+            got: 8
+        })
+    ));
+    assert!(BspFile::from_bytes(b"VBSP\x14\0\0\0").is_err());
+}
+
+#[cfg(feature = "compressed-input")]
+#[test]
+fn test_compressed_input() {
+    use std::io::Write;
+
+    let map = b"VBSP\x14\0\0\0".to_vec();
+
+    let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    gzip.write_all(&map).unwrap();
+    let mut bzip = bzip2::write::BzEncoder::new(vec![], bzip2::Compression::default());
+    bzip.write_all(&map).unwrap();
+
+    assert_eq!(decompress_input(gzip.finish().unwrap()).unwrap(), map);
+    assert_eq!(decompress_input(bzip.finish().unwrap()).unwrap(), map);
+    assert_eq!(decompress_input(map.clone()).unwrap(), map);
+}
+
+#[test]
+fn test_reject_nonfinite_floats() {
+    let data = fixture::FixtureBuilder::new()
+        .vertices(&[(0.0, 0.0, 0.0), (1.0, f32::NAN, 0.0), (2.0, 0.0, 0.0)])
+        .build();
+    let parser = BspParser { data: data.into() };
+
+    let lenient = LumpParser::parse_lump_data(parser.read_lump_info().unwrap(), &parser.data);
+    assert_eq!(lenient.vertex_list.len(), 3);
 
-    let lumps = bsp_parser.read_lump_info();
+    let strict = LumpParser::parse_lump_data_with_options(
+        parser.read_lump_info().unwrap(),
+        &parser.data,
+        &LumpParser::ParseOptions {
+            reject_nonfinite_floats: true,
+            ..Default::default()
+        },
+    );
+    assert_eq!(strict.vertex_list.len(), 1);
+    assert!(matches!(
+        strict.lump_errors[..],
+        [(
+            3,
+            BspError::NonFiniteFloat {
+                lump: 3,
+                offset: 16
+            }
+        )]
+    ));
+}
+
+#[test]
+fn test_parse_limits() {
+    let data = fixture::FixtureBuilder::new()
+        .vertices(&[(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (2.0, 0.0, 0.0)])
+        .build();
+    let parser = BspParser { data: data.into() };
+
+    let limited = LumpParser::parse_lump_data_with_options(
+        parser.read_lump_info().unwrap(),
+        &parser.data,
+        &LumpParser::ParseOptions {
+            limits: LumpParser::ParseLimits {
+                max_lump_elements: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    );
+    assert_eq!(limited.vertex_list.len(), 2);
+    assert!(matches!(
+        limited.lump_errors[..],
+        [(3, BspError::TooManyElements { lump: 3, limit: 2 })]
+    ));
 
-    println!(
-        "{:#?}",
-        LumpParser::parse_lump_data(lumps, &bsp_parser.data).physics_models
+    // The claimed size is checked before anything is decompressed
+    let mut block = b"LZMA".to_vec();
+    block.extend(&u32::MAX.to_le_bytes());
+    block.extend(&[0; 9]);
+    assert!(matches!(
+        LumpParser::decompress_block(&block, 1 << 20),
+        Err(BspError::DecompressedTooLarge { limit: 1048576, .. })
+    ));
+    // Paths without ParseOptions still get the default cap
+    assert!(matches!(
+        LumpParser::decompress_lump(&block),
+        Err(BspError::DecompressedTooLarge {
+            limit: LumpParser::DEFAULT_MAX_DECOMPRESSED_SIZE,
+            ..
+        })
+    ));
+    assert_eq!(
+        LumpParser::ParseLimits::default().max_decompressed_size,
+        LumpParser::DEFAULT_MAX_DECOMPRESSED_SIZE
     );
 }
+
+#[test]
+fn test_map_version_leaf_layout() {
+    // Two leaves in cluster 5, with the ambient cube when with_ambient is set
+    let leaves = |with_ambient: bool| {
+        let mut lump = vec![];
+        for _ in 0..2 {
+            lump.extend_from_slice(&0i32.to_le_bytes());
+            lump.extend_from_slice(&5i16.to_le_bytes());
+            lump.extend_from_slice(&[0; 24]);
+            if with_ambient {
+                lump.extend_from_slice(&[7; 24]);
+            }
+            lump.extend_from_slice(&[0; 2]);
+        }
+        lump
+    };
+
+    for (map_version, with_ambient) in [(19, true), (20, false)] {
+        let parser = BspParser {
+            data: fixture::FixtureBuilder::new()
+                .version(map_version)
+                .versioned_lump(LumpType::Leafs, 1, leaves(with_ambient))
+                .build()
+                .into(),
+        };
+        let lumps = LumpParser::parse_lump_data(parser.read_lump_info().unwrap(), &parser.data);
+
+        assert_eq!(lumps.version, map_version);
+        assert_eq!(lumps.leaves.len(), 2);
+        assert_eq!(lumps.leaves[1].cluster, 5);
+        assert_eq!(lumps.leaves[1].ambient_lighting.is_some(), with_ambient);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip() {
+    let parser = BspParser {
+        data: fixture::FixtureBuilder::new()
+            .entities("{\n\"classname\" \"worldspawn\"\n\"skyname\" \"sky_day01_01\"\n}\n")
+            .vertices(&[(0.0, 0.0, 0.0), (64.0, 0.0, 0.0), (64.0, 64.0, 0.0)])
+            .build()
+            .into(),
+    };
+    let lumps = LumpParser::parse_lump_data(parser.read_lump_info().unwrap(), &parser.data);
+
+    let json = serde_json::to_string(&lumps).unwrap();
+    let parsed: LumpParser::ParsedLumps = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(parsed.version, lumps.version);
+    assert_eq!(parsed.entities, lumps.entities);
+    assert_eq!(parsed.raw_entities, lumps.raw_entities);
+    assert_eq!(parsed.vertex_list, lumps.vertex_list);
+    assert_eq!(parsed.lump_meta, lumps.lump_meta);
+    // Errors aren't serialized
+    assert!(parsed.lump_errors.is_empty());
+}
+
+#[test]
+fn test_big_endian_map() {
+    // One plane, with its normal, distance and type big-endian
+    let mut plane = vec![];
+    for f in [0.0f32, 0.0, 1.0, 64.0] {
+        plane.extend_from_slice(&f.to_be_bytes());
+    }
+    plane.extend_from_slice(&2i32.to_be_bytes());
+
+    let parser = BspParser {
+        data: fixture::FixtureBuilder::new()
+            .big_endian()
+            .entities("{\n\"classname\" \"worldspawn\"\n}\n")
+            .vertices(&[(0.0, 0.0, 0.0), (64.0, 0.0, 0.0), (64.0, 64.0, 0.0)])
+            .lump(LumpType::Plane, plane)
+            .build()
+            .into(),
+    };
+    assert_eq!(&parser.data[0..4], b"PSBV");
+    assert_eq!(parser.endianness(), Endianness::Big);
+
+    let header = parser.fetch_header().unwrap();
+    assert_eq!(header.ident, VBSP_MAGIC);
+    assert_eq!(header.version, 20);
+    assert_eq!(header.map_revision, 1);
+
+    let lumps = LumpParser::parse_lump_data(parser.read_lump_info().unwrap(), &parser.data);
+    assert!(lumps.lump_errors.is_empty());
+    assert_eq!(lumps.endianness, Endianness::Big);
+    assert_eq!(lumps.version, 20);
+    assert_eq!(lumps.entities.len(), 1);
+    assert_eq!(lumps.vertex_list[2].y, 64.0);
+    assert_eq!(lumps.planes[0].normal, (0.0, 0.0, 1.0));
+    assert_eq!(lumps.planes[0].dist_from_origin, 64.0);
+    assert_eq!(lumps.planes[0].r#type, 2);
+
+    // The writer copies lumps as they're stored, so it can't write a little-endian header for them
+    assert!(matches!(
+        writer::BspWriter::from_parser(&parser),
+        Err(BspError::UnsupportedFormat { .. })
+    ));
+}
+
+#[test]
+fn test_parse_bytes() {
+    let mut data = fixture::FixtureBuilder::new()
+        .entities("{\n\"classname\" \"worldspawn\"\n}\n")
+        .vertices(&[(0.0, 0.0, 0.0), (64.0, 0.0, 0.0)])
+        .build();
+
+    let lumps = parse_bytes(&data).unwrap();
+    assert_eq!(lumps.entities.len(), 1);
+    assert_eq!(lumps.vertex_list.len(), 2);
+
+    assert!(matches!(
+        parse_bytes(&data[..100]),
+        Err(BspError::TruncatedHeader { .. })
+    ));
+
+    data[0..4].copy_from_slice(b"IBSP");
+    assert!(matches!(
+        parse_bytes(&data),
+        Err(BspError::UnsupportedFormat { .. })
+    ));
+    assert!(parse_file("does/not/exist.bsp").is_err());
+}
+
+#[test]
+fn test_parse_bytes_partial_element() {
+    // A lump ending partway through an element keeps the whole elements before it
+    let mut vertices = vec![0; 12];
+    vertices.extend_from_slice(&[0; 5]);
+    let mut physics = 0i32.to_le_bytes().to_vec();
+    physics.extend_from_slice(&100i32.to_le_bytes());
+    physics.extend_from_slice(&1000i32.to_le_bytes());
+    physics.extend_from_slice(&5i32.to_le_bytes());
+
+    let data = fixture::FixtureBuilder::new()
+        .lump(LumpType::Vertexes, vertices)
+        .lump(LumpType::Physcollide, physics)
+        .build();
+    let lumps = parse_bytes(&data).unwrap();
+
+    assert_eq!(lumps.vertex_list.len(), 1);
+    assert!(matches!(
+        lumps.lump_errors[..],
+        [(
+            3,
+            BspError::ShortRead {
+                offset: 12,
+                needed: 12,
+                available: 5
+            }
+        )]
+    ));
+    assert_eq!(lumps.physics_models.len(), 1);
+    assert!(lumps.physics_models[0].collision_data.is_empty());
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_open_mmap() {
+    let data = fixture::FixtureBuilder::new()
+        .entities("{\n\"classname\" \"worldspawn\"\n}\n")
+        .vertices(&[(0.0, 0.0, 0.0), (64.0, 0.0, 0.0)])
+        .build();
+    let path = std::env::temp_dir().join(format!("source_bsp_mmap_{}.bsp", std::process::id()));
+    std::fs::write(&path, &data).unwrap();
+
+    let parser = BspParser::open_mmap(&path).unwrap();
+    assert!(matches!(parser.data, MapData::Mapped(_)));
+    assert_eq!(&parser.data[..], &data[..]);
+
+    let lumps = LumpParser::parse_lump_data(parser.read_lump_info().unwrap(), &parser.data);
+    assert_eq!(lumps.entities[0]["classname"], "worldspawn");
+    assert_eq!(lumps.vertex_list.len(), 2);
+
+    drop(parser);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_entities_only() {
+    let mut data = fixture::FixtureBuilder::new()
+        .entities("{\n\"classname\" \"worldspawn\"\n}\n")
+        .build();
+    let path = std::env::temp_dir().join(format!("source_bsp_entities_{}.bsp", std::process::id()));
+
+    std::fs::write(&path, &data).unwrap();
+    let entities = BspParser::entities_only(&path).unwrap();
+    assert_eq!(entities[0]["classname"], "worldspawn");
+
+    // A length past the end of the file is rejected before anything is allocated for it
+    data[12..16].copy_from_slice(&i32::MAX.to_le_bytes());
+    std::fs::write(&path, &data).unwrap();
+    assert!(matches!(
+        BspParser::entities_only(&path),
+        Err(BspError::LumpOutOfBounds {
+            lump: 0,
+            length: i32::MAX,
+            ..
+        })
+    ));
+
+    std::fs::remove_file(&path).unwrap();
+}
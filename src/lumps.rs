@@ -1,13 +1,33 @@
-use std::collections::HashMap;
+use crate::error::BspError;
+
 use std::convert::TryInto;
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Lump {
     pub fileofs: i32,
     pub filelen: i32,
     pub version: i32,
+    /// Zero for an uncompressed lump, the decompressed size for an LZMA compressed one.
+    /// Always stored little-endian, whatever the map's byte order.
     pub ident: [u8; 4],
 }
 
+impl Lump {
+    /// Whether the lump is stored LZMA compressed, going by a decompressed size in the ident
+    pub fn is_compressed(&self) -> bool {
+        self.uncompressed_size().is_some()
+    }
+
+    /// The size of the lump once decompressed, None if it isn't compressed
+    pub fn uncompressed_size(&self) -> Option<u32> {
+        match u32::from_le_bytes(self.ident) {
+            0 => None,
+            size => Some(size),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum LumpType {
     Entities = 0,
     Plane = 1,
@@ -75,92 +95,361 @@ pub enum LumpType {
     DispMultiblend = 63,
 }
 
-pub struct LumpReader {
+// The lump directory has an entry for each lump type, so the two can't drift apart
+const _: () = assert!(LumpType::DispMultiblend as usize + 1 == crate::LUMP_COUNT);
+
+impl LumpType {
+    /// Every lump type, in directory order
+    const ALL: [LumpType; crate::LUMP_COUNT] = [
+        LumpType::Entities,
+        LumpType::Plane,
+        LumpType::Texdata,
+        LumpType::Vertexes,
+        LumpType::Visibility,
+        LumpType::Nodes,
+        LumpType::Texinfo,
+        LumpType::Faces,
+        LumpType::Lighting,
+        LumpType::Occlusion,
+        LumpType::Leafs,
+        LumpType::Faceids,
+        LumpType::Edges,
+        LumpType::Surfedges,
+        LumpType::Models,
+        LumpType::Worldlights,
+        LumpType::Leaffaces,
+        LumpType::Leafbrushes,
+        LumpType::Brushes,
+        LumpType::Brushsides,
+        LumpType::Areas,
+        LumpType::Areaportals,
+        LumpType::UNUSED0,
+        LumpType::UNUSED1,
+        LumpType::UNUSED2,
+        LumpType::UNUSED3,
+        LumpType::Dispinfo,
+        LumpType::Originalfaces,
+        LumpType::Physdisp,
+        LumpType::Physcollide,
+        LumpType::Vertnormals,
+        LumpType::Vertnormalindices,
+        LumpType::DispLightmapAlphas,
+        LumpType::DispVerts,
+        LumpType::DispLightmapSamplePositions,
+        LumpType::GameLump,
+        LumpType::Leafwaterdata,
+        LumpType::Primitives,
+        LumpType::Primverts,
+        LumpType::Primindicies,
+        LumpType::Pakfile,
+        LumpType::Clipportalverts,
+        LumpType::Cubemaps,
+        LumpType::TexdataStringData,
+        LumpType::TexdataStringTable,
+        LumpType::Overlays,
+        LumpType::Leafmindisttowater,
+        LumpType::FaceMacroTextureInfo,
+        LumpType::DispTris,
+        LumpType::Physcollidesurface,
+        LumpType::Wateroverlays,
+        LumpType::LeafAmbientIndexHDR,
+        LumpType::LeafAmbientIndex,
+        LumpType::LightingHDR,
+        LumpType::WorldlightsHDR,
+        LumpType::LeafAmbientLightingHDR,
+        LumpType::LeafAmbientLighting,
+        LumpType::Xzippakfile,
+        LumpType::FacesHDR,
+        LumpType::MapFlags,
+        LumpType::OverlayFades,
+        LumpType::OverlaySystemLevels,
+        LumpType::Physlevel,
+        LumpType::DispMultiblend,
+    ];
+
+    /// Returns the lump type at an index of the lump directory
+    pub fn from_index(index: usize) -> Option<LumpType> {
+        Self::ALL.get(index).copied()
+    }
+
+    /// The lump's index in the lump directory, the inverse of from_index
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LumpType::Entities => "Entities",
+            LumpType::Plane => "Plane",
+            LumpType::Texdata => "Texdata",
+            LumpType::Vertexes => "Vertexes",
+            LumpType::Visibility => "Visibility",
+            LumpType::Nodes => "Nodes",
+            LumpType::Texinfo => "Texinfo",
+            LumpType::Faces => "Faces",
+            LumpType::Lighting => "Lighting",
+            LumpType::Occlusion => "Occlusion",
+            LumpType::Leafs => "Leafs",
+            LumpType::Faceids => "Faceids",
+            LumpType::Edges => "Edges",
+            LumpType::Surfedges => "Surfedges",
+            LumpType::Models => "Models",
+            LumpType::Worldlights => "Worldlights",
+            LumpType::Leaffaces => "Leaffaces",
+            LumpType::Leafbrushes => "Leafbrushes",
+            LumpType::Brushes => "Brushes",
+            LumpType::Brushsides => "Brushsides",
+            LumpType::Areas => "Areas",
+            LumpType::Areaportals => "Areaportals",
+            LumpType::UNUSED0 => "UNUSED0",
+            LumpType::UNUSED1 => "UNUSED1",
+            LumpType::UNUSED2 => "UNUSED2",
+            LumpType::UNUSED3 => "UNUSED3",
+            LumpType::Dispinfo => "Dispinfo",
+            LumpType::Originalfaces => "Originalfaces",
+            LumpType::Physdisp => "Physdisp",
+            LumpType::Physcollide => "Physcollide",
+            LumpType::Vertnormals => "Vertnormals",
+            LumpType::Vertnormalindices => "Vertnormalindices",
+            LumpType::DispLightmapAlphas => "DispLightmapAlphas",
+            LumpType::DispVerts => "DispVerts",
+            LumpType::DispLightmapSamplePositions => "DispLightmapSamplePositions",
+            LumpType::GameLump => "GameLump",
+            LumpType::Leafwaterdata => "Leafwaterdata",
+            LumpType::Primitives => "Primitives",
+            LumpType::Primverts => "Primverts",
+            LumpType::Primindicies => "Primindicies",
+            LumpType::Pakfile => "Pakfile",
+            LumpType::Clipportalverts => "Clipportalverts",
+            LumpType::Cubemaps => "Cubemaps",
+            LumpType::TexdataStringData => "TexdataStringData",
+            LumpType::TexdataStringTable => "TexdataStringTable",
+            LumpType::Overlays => "Overlays",
+            LumpType::Leafmindisttowater => "Leafmindisttowater",
+            LumpType::FaceMacroTextureInfo => "FaceMacroTextureInfo",
+            LumpType::DispTris => "DispTris",
+            LumpType::Physcollidesurface => "Physcollidesurface",
+            LumpType::Wateroverlays => "Wateroverlays",
+            LumpType::LeafAmbientIndexHDR => "LeafAmbientIndexHDR",
+            LumpType::LeafAmbientIndex => "LeafAmbientIndex",
+            LumpType::LightingHDR => "LightingHDR",
+            LumpType::WorldlightsHDR => "WorldlightsHDR",
+            LumpType::LeafAmbientLightingHDR => "LeafAmbientLightingHDR",
+            LumpType::LeafAmbientLighting => "LeafAmbientLighting",
+            LumpType::Xzippakfile => "Xzippakfile",
+            LumpType::FacesHDR => "FacesHDR",
+            LumpType::MapFlags => "MapFlags",
+            LumpType::OverlayFades => "OverlayFades",
+            LumpType::OverlaySystemLevels => "OverlaySystemLevels",
+            LumpType::Physlevel => "Physlevel",
+            LumpType::DispMultiblend => "DispMultiblend",
+        }
+    }
+}
+
+/// The byte order of a map's values. PC maps are little-endian, while the Xbox 360
+/// and PS3 ports store them big-endian with the magic written as "PSBV".
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endianness {
+    /// Works out the byte order from the magic at the start of a map,
+    /// None if it isn't a VBSP file
+    pub fn of_map(data: &[u8]) -> Option<Self> {
+        match data.get(0..4)? {
+            b"VBSP" => Some(Endianness::Little),
+            b"PSBV" => Some(Endianness::Big),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a number from the next bytes in the reader's byte order
+macro_rules! read_number {
+    ($reader:expr, $kind:ty) => {{
+        let bytes = $reader.take()?;
+        match $reader.endianness {
+            Endianness::Little => <$kind>::from_le_bytes(bytes),
+            Endianness::Big => <$kind>::from_be_bytes(bytes),
+        }
+    }};
+}
+
+/// Reads the values of a lump from a borrowed slice of it, little-endian unless set otherwise
+pub struct LumpReader<'a> {
     position: usize,
-    data: Vec<u8>,
+    data: &'a [u8],
+    endianness: Endianness,
+    reject_nonfinite: bool,
+    nonfinite_at: Option<usize>,
 }
 
-impl LumpReader {
-    pub fn new(data: &[u8]) -> Self {
+impl<'a> LumpReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
         LumpReader {
             position: 0,
-            data: data.to_vec(),
+            data,
+            endianness: Endianness::Little,
+            reject_nonfinite: false,
+            nonfinite_at: None,
         }
     }
 
-    pub fn read_f32(&mut self) -> f32 {
-        self.position += 4;
-        f32::from_le_bytes(
-            self.data[self.position - 4..self.position]
-                .try_into()
-                .unwrap(),
-        )
+    /// Sets the byte order numbers are read in
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness;
     }
 
-    pub fn read_i32(&mut self) -> i32 {
-        self.position += 4;
-        i32::from_le_bytes(
-            self.data[self.position - 4..self.position]
-                .try_into()
-                .unwrap(),
-        )
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
     }
 
-    pub fn read_u32(&mut self) -> u32 {
-        self.position += 4;
-        u32::from_le_bytes(
-            self.data[self.position - 4..self.position]
-                .try_into()
-                .unwrap(),
-        )
+    /// Takes the next N bytes, failing without moving if there aren't that many left
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], BspError> {
+        let bytes = self
+            .data
+            .get(self.position..)
+            .and_then(|rest| rest.get(..N))
+            .ok_or(BspError::ShortRead {
+                offset: self.position,
+                needed: N,
+                available: self.remaining(),
+            })?;
+        self.position += N;
+
+        Ok(bytes.try_into().unwrap())
     }
 
-    pub fn read_x_u32(&mut self, count: usize) -> Vec<u32> {
-        let mut out = vec![];
-        for _ in 0..count {
-            out.push(self.read_u32());
+    pub fn try_read_f32(&mut self) -> Result<f32, BspError> {
+        let value = read_number!(self, f32);
+
+        if self.reject_nonfinite && !value.is_finite() && self.nonfinite_at.is_none() {
+            self.nonfinite_at = Some(self.position - 4);
         }
-        out
+
+        Ok(value)
+    }
+
+    pub fn try_read_i32(&mut self) -> Result<i32, BspError> {
+        Ok(read_number!(self, i32))
+    }
+
+    pub fn try_read_u32(&mut self) -> Result<u32, BspError> {
+        Ok(read_number!(self, u32))
+    }
+
+    pub fn try_read_u16(&mut self) -> Result<u16, BspError> {
+        Ok(read_number!(self, u16))
+    }
+
+    pub fn try_read_i16(&mut self) -> Result<i16, BspError> {
+        Ok(read_number!(self, i16))
+    }
+
+    pub fn try_read_u8(&mut self) -> Result<u8, BspError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn try_read_i8(&mut self) -> Result<i8, BspError> {
+        Ok(self.take::<1>()?[0] as i8)
+    }
+
+    // The infallible reads panic on running past the end of the data, with a message
+    // giving the offset and how many bytes were left
+
+    pub fn read_f32(&mut self) -> f32 {
+        self.try_read_f32().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn read_i32(&mut self) -> i32 {
+        self.try_read_i32().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn read_u32(&mut self) -> u32 {
+        self.try_read_u32().unwrap_or_else(|e| panic!("{}", e))
     }
 
     pub fn read_u16(&mut self) -> u16 {
-        self.position += 2;
-        u16::from_le_bytes(
-            self.data[self.position - 2..self.position]
-                .try_into()
-                .unwrap(),
-        )
+        self.try_read_u16().unwrap_or_else(|e| panic!("{}", e))
     }
 
     pub fn read_i16(&mut self) -> i16 {
-        self.position += 2;
-        i16::from_le_bytes(
-            self.data[self.position - 2..self.position]
-                .try_into()
-                .unwrap(),
-        )
+        self.try_read_i16().unwrap_or_else(|e| panic!("{}", e))
     }
 
     pub fn read_u8(&mut self) -> u8 {
-        self.position += 1;
-        u8::from_le_bytes(
-            self.data[self.position - 1..self.position]
-                .try_into()
-                .unwrap(),
-        )
+        self.try_read_u8().unwrap_or_else(|e| panic!("{}", e))
     }
 
     pub fn read_i8(&mut self) -> i8 {
-        self.position += 1;
-        i8::from_le_bytes(
-            self.data[self.position - 1..self.position]
-                .try_into()
-                .unwrap(),
-        )
+        self.try_read_i8().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Makes the reader remember where it first reads a NaN or infinite float
+    pub fn set_reject_nonfinite(&mut self, reject: bool) {
+        self.reject_nonfinite = reject;
+    }
+
+    /// Offset of the first NaN or infinite float read, if set_reject_nonfinite is on
+    pub fn nonfinite_float(&self) -> Option<usize> {
+        self.nonfinite_at
+    }
+
+    pub fn read_x_u32(&mut self, count: usize) -> Vec<u32> {
+        self.read_array(count)
+    }
+
+    /// Reads count elements one after another. A count larger than could fit in the
+    /// remaining bytes is cut down to what fits, so a corrupt count can't allocate more
+    /// than the lump holds.
+    pub fn read_array<T: BspParseable>(&mut self, count: usize) -> Vec<T> {
+        let count = count.min(self.remaining() / T::MIN_SIZE);
+        (0..count).map(|_| T::from_reader(self)).collect()
+    }
+
+    /// Reads count elements like read_array, but fails without reading anything if they
+    /// can't fit in the remaining bytes
+    pub fn try_read_array<T: BspParseable>(&mut self, count: usize) -> Result<Vec<T>, BspError> {
+        let needed = count.saturating_mul(T::MIN_SIZE);
+        if needed > self.remaining() {
+            return Err(BspError::ShortRead {
+                offset: self.position,
+                needed,
+                available: self.remaining(),
+            });
+        }
+
+        Ok((0..count).map(|_| T::from_reader(self)).collect())
+    }
+
+    /// Reads three floats, the layout of every vector in the lumps
+    pub fn read_vec3(&mut self) -> (f32, f32, f32) {
+        (self.read_f32(), self.read_f32(), self.read_f32())
+    }
+
+    /// Reads three shorts, used for the bounding boxes of nodes and leaves
+    pub fn read_vec3i16(&mut self) -> [i16; 3] {
+        [self.read_i16(), self.read_i16(), self.read_i16()]
+    }
+
+    /// Reads four floats, used for texture and lightmap projection vectors
+    pub fn read_vec4f32(&mut self) -> [f32; 4] {
+        [
+            self.read_f32(),
+            self.read_f32(),
+            self.read_f32(),
+            self.read_f32(),
+        ]
     }
 
-    pub fn get_data(&self) -> &[u8] {
-        &self.data
+    /// The whole slice being read, which outlives the reader
+    pub fn get_data(&self) -> &'a [u8] {
+        self.data
     }
 
     pub fn get_pos(&self) -> usize {
@@ -174,18 +463,164 @@ impl LumpReader {
     pub fn get_len(&self) -> usize {
         self.data.len()
     }
+
+    /// Number of bytes left to read, 0 if a read has already gone past the end
+    pub fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.position)
+    }
+
+    pub fn at_end(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Splits off the next len bytes (or as many as are left) into their own reader
+    /// with the same byte order, and moves this reader past them
+    pub fn with_limit(&mut self, len: usize) -> LumpReader<'a> {
+        let len = len.min(self.remaining());
+        let start = self.position.min(self.data.len());
+        self.position += len;
+
+        let mut reader = LumpReader::new(&self.data[start..start + len]);
+        reader.set_endianness(self.endianness);
+        reader
+    }
+}
+
+/// Slices `count` items starting at `first`, the way lumps reference ranges of other lumps.
+/// Errors if any of the range is outside of the slice, as it is in a malformed map.
+pub fn checked_slice<T>(arr: &[T], first: i64, count: i64) -> Result<&[T], BspError> {
+    let error = BspError::SliceOutOfRange {
+        first,
+        count,
+        len: arr.len(),
+    };
+
+    if first < 0 || count < 0 {
+        return Err(error);
+    }
+
+    let first = first as usize;
+    first
+        .checked_add(count as usize)
+        .and_then(|end| arr.get(first..end))
+        .ok_or(error)
 }
 
+/// Like checked_slice, but clamps the range to the slice. A negative first or count gives nothing.
+pub fn clamped_slice<T>(arr: &[T], first: i64, count: i64) -> &[T] {
+    if first < 0 || count < 0 {
+        return &[];
+    }
+
+    let first = (first as usize).min(arr.len());
+    let end = first.saturating_add(count as usize).min(arr.len());
+    &arr[first..end]
+}
+
+#[cfg(feature = "cache")]
+pub mod cache;
+pub mod compact;
+pub mod coords;
+pub mod diff;
+pub mod displacement;
+pub mod entities;
+pub mod game_lump;
+pub mod geometry;
+pub mod lighting;
 pub mod lump_types;
+pub(crate) mod math;
+pub mod overlays;
+pub mod pakfile;
+pub mod trace;
+pub mod validate;
+pub mod visibility;
+use entities::*;
+use game_lump::*;
 use lump_types::*;
+use pakfile::*;
+use visibility::*;
 
+#[allow(non_snake_case)]
 pub mod LumpParser {
+    use crate::error::BspError;
     use crate::lumps::*;
-    use regex::Regex;
 
-    #[derive(Debug, Default)]
+    use std::collections::HashMap;
+
+    /// Surfaces that are never drawn as regular world geometry
+    pub(crate) const TOOL_SURFACES: SurfaceFlags = SurfaceFlags(
+        SurfaceFlags::SKY.0
+            | SurfaceFlags::NODRAW.0
+            | SurfaceFlags::SKIP.0
+            | SurfaceFlags::HINT.0
+            | SurfaceFlags::TRIGGER.0,
+    );
+
+    /// Largest a lump may decompress to unless ParseLimits says otherwise. The biggest lumps
+    /// in shipped maps, the pakfile and lighting, are well under this.
+    pub const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 256 << 20;
+
+    /// Caps on how much a map can make the parser allocate. By default only decompression is
+    /// capped, at DEFAULT_MAX_DECOMPRESSED_SIZE. Tighten them when parsing maps from untrusted
+    /// sources such as user uploads.
+    #[derive(Debug, Clone)]
+    pub struct ParseLimits {
+        /// Most elements read from a single lump. The rest of the lump is skipped and
+        /// reported in lump_errors.
+        pub max_lump_elements: usize,
+        /// Largest size in bytes a compressed lump or game lump may decompress to.
+        /// The size is read from the compressed header, so this is checked before allocating.
+        pub max_decompressed_size: usize,
+    }
+
+    impl Default for ParseLimits {
+        fn default() -> Self {
+            ParseLimits {
+                max_lump_elements: usize::MAX,
+                max_decompressed_size: DEFAULT_MAX_DECOMPRESSED_SIZE,
+            }
+        }
+    }
+
+    /// Settings for how the lumps are parsed
+    #[derive(Debug, Clone, Default)]
+    pub struct ParseOptions {
+        /// Keep the original bytes of compressed lumps in LumpMeta, so the map can be
+        /// written back out without recompressing. Off by default to save memory.
+        pub keep_compressed: bool,
+        /// Stop parsing a lump at the first NaN or infinite float and report it in
+        /// lump_errors, rather than passing the value on. Off by default.
+        pub reject_nonfinite_floats: bool,
+        pub limits: ParseLimits,
+    }
+
+    /// What the lump directory says about a lump
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct LumpMeta {
+        /// Whether the map includes the lump at all
+        pub present: bool,
+        /// Lump format version
+        pub version: i32,
+        /// Whether the lump is LZMA compressed in the file
+        pub compressed: bool,
+        /// Size of the lump's contents once decompressed
+        pub length: usize,
+        /// The lump's bytes as stored in the file, only kept with ParseOptions::keep_compressed
+        pub compressed_data: Option<Vec<u8>>,
+    }
+
+    #[derive(Debug, Clone, Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct ParsedLumps {
+        /// Version of the map from its header, which some lump layouts depend on
+        pub version: i32,
+        /// Byte order of the map, which the game lumps decoded on demand are read in
+        pub endianness: Endianness,
         pub entities: Vec<Entity>,
+        /// The entities with their keys in order, including repeated ones. Edits belong in
+        /// `entities`, these only give the key layout when the entities are written back.
+        pub raw_entities: Vec<RawEntity>,
         pub planes: Vec<Plane>,
         pub texdata: Vec<TexData>,
         pub vertex_list: Vec<Vertex>,
@@ -206,178 +641,1216 @@ pub mod LumpParser {
         pub displacement_info: Vec<DisplacementInfo>,
         pub original_faces: Vec<Face>,
         pub physics_models: Vec<PhysicsModel>,
+        pub disp_verts: Vec<DispVert>,
+        pub overlays: Vec<Overlay>,
+        pub cubemaps: Vec<Cubemap>,
+        /// Lights baked into the LDR and HDR lightmaps
+        pub worldlights: Vec<WorldLight>,
+        pub worldlights_hdr: Vec<WorldLight>,
+        pub visibility: Option<Visibility>,
+        pub pakfile: Option<Pakfile>,
+        pub leaves: Vec<Leaf>,
+        /// Each leaf's range of ambient samples, for the LDR and HDR lighting
+        pub leaf_ambient_index: Vec<LeafAmbientIndex>,
+        pub leaf_ambient_index_hdr: Vec<LeafAmbientIndex>,
+        /// Ambient samples of every leaf, for the LDR and HDR lighting
+        pub leaf_ambient_lighting: Vec<LeafAmbientLighting>,
+        pub leaf_ambient_lighting_hdr: Vec<LeafAmbientLighting>,
+        /// One alpha byte per luxel of each displacement
+        pub disp_lightmap_alphas: Vec<u8>,
+        /// Variable length encoded sample positions of each displacement's luxels
+        pub disp_lightmap_sample_positions: Vec<u8>,
+        /// NUL terminated material names
+        pub texdata_string_data: Vec<u8>,
+        /// Offsets into texdata_string_data
+        pub texdata_string_table: Vec<i32>,
+        /// Sub-lumps of the game lump
+        pub game_lumps: Vec<GameLump>,
+        /// Directory information for each lump, by lump index
+        pub lump_meta: Vec<LumpMeta>,
+        /// Lumps that couldn't be parsed, by lump index
+        #[cfg_attr(feature = "serde", serde(skip))]
+        pub lump_errors: Vec<(usize, BspError)>,
+    }
+
+    impl ParsedLumps {
+        /// True if the map includes the lump, whether or not this crate parses it
+        pub fn has_lump(&self, lump_type: LumpType) -> bool {
+            self.lump_meta
+                .get(lump_type as usize)
+                .is_some_and(|meta| meta.present)
+        }
+
+        /// Lists the lumps the map includes, in directory order
+        pub fn present_lumps(&self) -> Vec<LumpType> {
+            self.lump_meta
+                .iter()
+                .enumerate()
+                .filter(|(_, meta)| meta.present)
+                .filter_map(|(i, _)| LumpType::from_index(i))
+                .collect()
+        }
+
+        /// Returns the plane at an index, as referenced by nodes, faces and brush sides
+        pub fn plane(&self, index: usize) -> Option<&Plane> {
+            self.planes.get(index)
+        }
+
+        /// Returns the displacement info for a face, or None if the face isn't a displacement
+        pub fn face_displacement(&self, face: &Face) -> Option<&DisplacementInfo> {
+            if face.displacement_info < 0 {
+                return None; // -1 marks a regular face
+            }
+            self.displacement_info.get(face.displacement_info as usize)
+        }
+
+        /// Returns the face a displacement was built from
+        pub fn displacement_base_face(&self, disp: &DisplacementInfo) -> Option<&Face> {
+            self.faces.get(disp.map_face as usize)
+        }
+
+        /// Resolves the vertices of a face in winding order by following its surfedges
+        pub fn face_vertices(&self, face: &Face) -> Vec<Vertex> {
+            self.face_vertex_indices(face)
+                .into_iter()
+                .filter_map(|index| self.vertex_list.get(index).cloned())
+                .collect()
+        }
+
+        /// Like face_vertices, but returns indices into `vertex_list` so shared corners
+        /// can be recognized. The indices aren't checked against the vertex list.
+        pub fn face_vertex_indices(&self, face: &Face) -> Vec<usize> {
+            let mut out = vec![];
+
+            if face.first_edge < 0 {
+                return out;
+            }
+
+            let surfedges = clamped_slice(
+                &self.surfedges,
+                face.first_edge as i64,
+                face.num_edges as i64,
+            );
+
+            for &surfedge in surfedges {
+                // A negative surfedge walks the edge backwards, so its second vertex comes first
+                let vertex_index = match self.edges.get(surfedge.unsigned_abs() as usize) {
+                    Some(edge) if surfedge >= 0 => edge.vertex_indicies[0],
+                    Some(edge) => edge.vertex_indicies[1],
+                    None => break,
+                };
+
+                out.push(vertex_index as usize);
+            }
+
+            out
+        }
+
+        /// Iterates over the faces of the world, model 0, along with their vertices
+        /// from face_vertices. Brush entities' faces aren't included.
+        pub fn world_faces(&self) -> impl Iterator<Item = (&Face, Vec<Vertex>)> {
+            self.model_faces(0)
+                .iter()
+                .map(move |face| (face, self.face_vertices(face)))
+        }
+
+        /// Returns the material name used by a TexData
+        pub fn texture_name(&self, tex_data: &TexData) -> Option<&str> {
+            let offset = *self
+                .texdata_string_table
+                .get(tex_data.texdata_string_table_index as usize)?;
+
+            let string = self.texdata_string_data.get(offset as usize..)?;
+            let end = string.iter().position(|&c| c == 0).unwrap_or(string.len());
+
+            std::str::from_utf8(&string[..end]).ok()
+        }
+
+        /// Returns the surface flags of a face, empty if it has no texinfo
+        pub fn face_surface_flags(&self, face: &Face) -> SurfaceFlags {
+            self.texinfo
+                .get(face.texinfo as usize)
+                .map_or(SurfaceFlags(0), |texinfo| texinfo.surface_flags())
+        }
+
+        /// Iterates over the faces that should be rendered, skipping sky and tool textures
+        pub fn visible_faces(&self) -> impl Iterator<Item = &Face> {
+            self.faces
+                .iter()
+                .filter(move |face| !self.face_surface_flags(face).intersects(TOOL_SURFACES))
+        }
+
+        /// Iterates over the sky and tool textured faces that visible_faces skips
+        pub fn tool_faces(&self) -> impl Iterator<Item = &Face> {
+            self.faces
+                .iter()
+                .filter(move |face| self.face_surface_flags(face).intersects(TOOL_SURFACES))
+        }
+
+        /// Returns the material name of a face
+        pub fn face_texture_name(&self, face: &Face) -> Option<&str> {
+            let texinfo = self.texinfo.get(face.texinfo as usize)?;
+            self.texture_name(self.texdata.get(texinfo.tex_data as usize)?)
+        }
+
+        /// Sums the area of every visible face
+        pub fn total_surface_area(&self) -> f32 {
+            self.visible_faces().map(|face| face.area).sum()
+        }
+
+        /// Sums the area of the visible faces using each material.
+        /// Faces whose material can't be resolved are grouped under an empty name.
+        pub fn surface_area_by_material(&self) -> HashMap<String, f32> {
+            let mut areas = HashMap::new();
+
+            for face in self.visible_faces() {
+                let name = self.face_texture_name(face).unwrap_or("");
+                *areas.entry(name.to_string()).or_insert(0.0) += face.area;
+            }
+
+            areas
+        }
+
+        /// Iterates over the brushes with any of the contents in the mask,
+        /// e.g. SOLID | PLAYERCLIP for what players collide with
+        pub fn brushes_with_contents(&self, mask: ContentsFlags) -> impl Iterator<Item = &Brush> {
+            self.brushes
+                .iter()
+                .filter(move |brush| brush.contents_flags().intersects(mask))
+        }
+
+        /// Maps each visibility cluster to the indices of the leaves in it.
+        /// Leaves outside of any cluster (-1) aren't included.
+        pub fn cluster_leaves(&self) -> HashMap<i16, Vec<usize>> {
+            let mut clusters: HashMap<i16, Vec<usize>> = HashMap::new();
+
+            for (i, leaf) in self.leaves.iter().enumerate() {
+                if leaf.cluster >= 0 {
+                    clusters.entry(leaf.cluster).or_default().push(i);
+                }
+            }
+
+            clusters
+        }
+
+        /// Returns the faces of a brush model, model 0 being the world itself.
+        /// A range running past the faces is clamped, see try_model_faces to catch it.
+        pub fn model_faces(&self, model_index: usize) -> &[Face] {
+            match self.models.get(model_index) {
+                Some(model) => {
+                    clamped_slice(&self.faces, model.first_face as i64, model.num_faces as i64)
+                }
+                None => &[],
+            }
+        }
+
+        /// Returns the faces of a brush model, or an error if the model or its faces are out of range
+        pub fn try_model_faces(&self, model_index: usize) -> Result<&[Face], BspError> {
+            let model = checked_slice(&self.models, model_index as i64, 1)?;
+            checked_slice(
+                &self.faces,
+                model[0].first_face as i64,
+                model[0].num_faces as i64,
+            )
+        }
+
+        /// Returns the indices into `faces` of the faces in a leaf, clamped to the leaf faces
+        pub fn leaf_faces(&self, leaf_index: usize) -> &[u16] {
+            match self.leaves.get(leaf_index) {
+                Some(leaf) => clamped_slice(
+                    &self.leaf_faces,
+                    leaf.first_leaf_face as i64,
+                    leaf.num_leaf_faces as i64,
+                ),
+                None => &[],
+            }
+        }
+
+        /// Returns the indices into `faces` of the faces in a leaf, or an error if they're out of range
+        pub fn try_leaf_faces(&self, leaf_index: usize) -> Result<&[u16], BspError> {
+            let leaf = checked_slice(&self.leaves, leaf_index as i64, 1)?;
+            checked_slice(
+                &self.leaf_faces,
+                leaf[0].first_leaf_face as i64,
+                leaf[0].num_leaf_faces as i64,
+            )
+        }
+
+        /// Returns the sides of a brush, clamped to the brush sides
+        pub fn brush_sides(&self, brush_index: usize) -> &[Brushside] {
+            match self.brushes.get(brush_index) {
+                Some(brush) => clamped_slice(
+                    &self.brushsides,
+                    brush.first_side as i64,
+                    brush.num_sides as i64,
+                ),
+                None => &[],
+            }
+        }
+
+        /// Returns the sides of a brush, or an error if the brush or its sides are out of range
+        pub fn try_brush_sides(&self, brush_index: usize) -> Result<&[Brushside], BspError> {
+            let brush = checked_slice(&self.brushes, brush_index as i64, 1)?;
+            checked_slice(
+                &self.brushsides,
+                brush[0].first_side as i64,
+                brush[0].num_sides as i64,
+            )
+        }
+
+        /// Returns the faces of the brush model referenced by an entity's "model" "*N" key
+        pub fn entity_brush_faces(&self, entity: &Entity) -> Option<&[Face]> {
+            let model_index = brush_model_index(entity)?;
+            self.models.get(model_index)?;
+
+            Some(self.model_faces(model_index))
+        }
+
+        /// Pairs each brush model other than the world with the entity using it, like
+        /// func_brush or trigger_multiple. func_detail brushes are merged into the world
+        /// when compiling, so they don't have models of their own.
+        pub fn entity_models(&self) -> Vec<(usize, &Entity)> {
+            self.entities
+                .iter()
+                .filter_map(|entity| Some((brush_model_index(entity)?, entity)))
+                .filter(|&(model_index, _)| model_index != 0 && model_index < self.models.len())
+                .collect()
+        }
+    }
+
+    /// Reads the model index from an entity's "model" key. Brush models are "*N",
+    /// anything else is a path to a studio model.
+    fn brush_model_index(entity: &Entity) -> Option<usize> {
+        entity.get("model")?.strip_prefix('*')?.parse().ok()
+    }
+
+    /// Receives each item as it is parsed out of the lumps.
+    ///
+    /// Every callback defaults to doing nothing, so a visitor only implements the ones it cares
+    /// about and everything else is dropped as soon as it's read.
+    pub trait LumpVisitor {
+        /// Called with the map version from the header, before any lumps
+        fn on_version(&mut self, _version: i32) {}
+        /// Called with the map's byte order, before any lumps
+        fn on_endianness(&mut self, _endianness: Endianness) {}
+        fn on_entity(&mut self, _entity: Entity) {}
+        /// Called after on_entity with the same entity, keeping its key order and repeated keys
+        fn on_raw_entity(&mut self, _entity: RawEntity) {}
+        fn on_plane(&mut self, _plane: Plane) {}
+        fn on_texdata(&mut self, _texdata: TexData) {}
+        fn on_vertex(&mut self, _vertex: Vertex) {}
+        fn on_node(&mut self, _node: Node) {}
+        fn on_texinfo(&mut self, _texinfo: TexInfo) {}
+        fn on_face(&mut self, _face: Face) {}
+        fn on_lightmap_sample(&mut self, _sample: LightmapSample) {}
+        fn on_occluder(&mut self, _occluder: Occluder) {}
+        fn on_leaf(&mut self, _leaf: Leaf) {}
+        fn on_leaf_ambient_index(&mut self, _index: LeafAmbientIndex) {}
+        fn on_leaf_ambient_index_hdr(&mut self, _index: LeafAmbientIndex) {}
+        fn on_leaf_ambient_lighting(&mut self, _lighting: LeafAmbientLighting) {}
+        fn on_leaf_ambient_lighting_hdr(&mut self, _lighting: LeafAmbientLighting) {}
+        fn on_edge(&mut self, _edge: Edge) {}
+        fn on_surfedge(&mut self, _surfedge: i32) {}
+        fn on_model(&mut self, _model: Model) {}
+        fn on_leaf_face(&mut self, _leaf_face: u16) {}
+        fn on_leaf_brush(&mut self, _leaf_brush: u16) {}
+        fn on_brush(&mut self, _brush: Brush) {}
+        fn on_brushside(&mut self, _brushside: Brushside) {}
+        fn on_area(&mut self, _area: Area) {}
+        fn on_area_portal(&mut self, _area_portal: AreaPortal) {}
+        fn on_displacement_info(&mut self, _info: DisplacementInfo) {}
+        fn on_original_face(&mut self, _face: Face) {}
+        fn on_physics_model(&mut self, _model: PhysicsModel) {}
+        fn on_disp_vert(&mut self, _vert: DispVert) {}
+        fn on_overlay(&mut self, _overlay: Overlay) {}
+        fn on_cubemap(&mut self, _cubemap: Cubemap) {}
+        fn on_worldlight(&mut self, _light: WorldLight) {}
+        fn on_worldlight_hdr(&mut self, _light: WorldLight) {}
+        fn on_visibility(&mut self, _visibility: Visibility) {}
+        fn on_pakfile(&mut self, _pakfile: &[u8]) {}
+        fn on_disp_lightmap_alphas(&mut self, _alphas: &[u8]) {}
+        fn on_disp_lightmap_sample_positions(&mut self, _positions: &[u8]) {}
+        fn on_texdata_string_data(&mut self, _data: &[u8]) {}
+        fn on_texdata_string_table_entry(&mut self, _offset: i32) {}
+        fn on_game_lump(&mut self, _game_lump: GameLump) {}
+        /// Called for every entry in the lump directory, before any of the lump's contents
+        fn on_lump_meta(&mut self, _lump: usize, _meta: LumpMeta) {}
+        /// Called instead of the lump's other callbacks when it can't be parsed
+        fn on_lump_error(&mut self, _lump: usize, _error: BspError) {}
+    }
+
+    /// Collects everything into the ParsedLumps
+    impl LumpVisitor for ParsedLumps {
+        fn on_version(&mut self, version: i32) {
+            self.version = version
+        }
+        fn on_endianness(&mut self, endianness: Endianness) {
+            self.endianness = endianness
+        }
+        fn on_entity(&mut self, entity: Entity) {
+            self.entities.push(entity)
+        }
+        fn on_raw_entity(&mut self, entity: RawEntity) {
+            self.raw_entities.push(entity)
+        }
+        fn on_plane(&mut self, plane: Plane) {
+            self.planes.push(plane)
+        }
+        fn on_texdata(&mut self, texdata: TexData) {
+            self.texdata.push(texdata)
+        }
+        fn on_vertex(&mut self, vertex: Vertex) {
+            self.vertex_list.push(vertex)
+        }
+        fn on_node(&mut self, node: Node) {
+            self.nodes.push(node)
+        }
+        fn on_texinfo(&mut self, texinfo: TexInfo) {
+            self.texinfo.push(texinfo)
+        }
+        fn on_face(&mut self, face: Face) {
+            self.faces.push(face)
+        }
+        fn on_lightmap_sample(&mut self, sample: LightmapSample) {
+            self.lightmap_samples.push(sample)
+        }
+        fn on_occluder(&mut self, occluder: Occluder) {
+            self.occluders.push(occluder)
+        }
+        fn on_leaf(&mut self, leaf: Leaf) {
+            self.leaves.push(leaf)
+        }
+        fn on_leaf_ambient_index(&mut self, index: LeafAmbientIndex) {
+            self.leaf_ambient_index.push(index)
+        }
+        fn on_leaf_ambient_index_hdr(&mut self, index: LeafAmbientIndex) {
+            self.leaf_ambient_index_hdr.push(index)
+        }
+        fn on_leaf_ambient_lighting(&mut self, lighting: LeafAmbientLighting) {
+            self.leaf_ambient_lighting.push(lighting)
+        }
+        fn on_leaf_ambient_lighting_hdr(&mut self, lighting: LeafAmbientLighting) {
+            self.leaf_ambient_lighting_hdr.push(lighting)
+        }
+        fn on_edge(&mut self, edge: Edge) {
+            self.edges.push(edge)
+        }
+        fn on_surfedge(&mut self, surfedge: i32) {
+            self.surfedges.push(surfedge)
+        }
+        fn on_model(&mut self, model: Model) {
+            self.models.push(model)
+        }
+        fn on_leaf_face(&mut self, leaf_face: u16) {
+            self.leaf_faces.push(leaf_face)
+        }
+        fn on_leaf_brush(&mut self, leaf_brush: u16) {
+            self.leaf_brushes.push(leaf_brush)
+        }
+        fn on_brush(&mut self, brush: Brush) {
+            self.brushes.push(brush)
+        }
+        fn on_brushside(&mut self, brushside: Brushside) {
+            self.brushsides.push(brushside)
+        }
+        fn on_area(&mut self, area: Area) {
+            self.areas.push(area)
+        }
+        fn on_area_portal(&mut self, area_portal: AreaPortal) {
+            self.area_portals.push(area_portal)
+        }
+        fn on_displacement_info(&mut self, info: DisplacementInfo) {
+            self.displacement_info.push(info)
+        }
+        fn on_original_face(&mut self, face: Face) {
+            self.original_faces.push(face)
+        }
+        fn on_physics_model(&mut self, model: PhysicsModel) {
+            self.physics_models.push(model)
+        }
+        fn on_disp_vert(&mut self, vert: DispVert) {
+            self.disp_verts.push(vert)
+        }
+        fn on_overlay(&mut self, overlay: Overlay) {
+            self.overlays.push(overlay)
+        }
+        fn on_cubemap(&mut self, cubemap: Cubemap) {
+            self.cubemaps.push(cubemap)
+        }
+        fn on_worldlight(&mut self, light: WorldLight) {
+            self.worldlights.push(light)
+        }
+        fn on_worldlight_hdr(&mut self, light: WorldLight) {
+            self.worldlights_hdr.push(light)
+        }
+        fn on_visibility(&mut self, visibility: Visibility) {
+            self.visibility = Some(visibility)
+        }
+        fn on_pakfile(&mut self, pakfile: &[u8]) {
+            self.pakfile = Some(Pakfile {
+                data: pakfile.to_vec(),
+            })
+        }
+        fn on_disp_lightmap_alphas(&mut self, alphas: &[u8]) {
+            self.disp_lightmap_alphas = alphas.to_vec()
+        }
+        fn on_disp_lightmap_sample_positions(&mut self, positions: &[u8]) {
+            self.disp_lightmap_sample_positions = positions.to_vec()
+        }
+        fn on_texdata_string_data(&mut self, data: &[u8]) {
+            self.texdata_string_data = data.to_vec()
+        }
+        fn on_texdata_string_table_entry(&mut self, offset: i32) {
+            self.texdata_string_table.push(offset)
+        }
+        fn on_game_lump(&mut self, game_lump: GameLump) {
+            self.game_lumps.push(game_lump)
+        }
+        fn on_lump_meta(&mut self, _lump: usize, meta: LumpMeta) {
+            self.lump_meta.push(meta)
+        }
+        fn on_lump_error(&mut self, lump: usize, error: BspError) {
+            self.lump_errors.push((lump, error))
+        }
+    }
+
+    /// The most elements parse_type reads from one lump, and the lump to blame past that
+    #[derive(Clone, Copy)]
+    struct ElementLimit {
+        lump: usize,
+        max: usize,
     }
 
     macro_rules! parse_type {
-        ($data:expr, $dst:expr, $kind:ty) => {{
+        ($data:expr, $limit:expr, $visitor:ident.$callback:ident, size $size:expr, read $read:expr) => {{
+            let size = $size;
+            let mut count = 0;
             while $data.get_pos() < $data.get_len() {
-                // Pushes the data read to the destination
-                $dst.push(<$kind>::from_reader(&mut $data));
+                if count == $limit.max {
+                    $visitor.on_lump_error(
+                        $limit.lump,
+                        BspError::TooManyElements {
+                            lump: $limit.lump,
+                            limit: $limit.max,
+                        },
+                    );
+                    break;
+                }
+                // A lump ending partway through an element, the elements before it are kept
+                if $data.remaining() < size {
+                    $visitor.on_lump_error(
+                        $limit.lump,
+                        BspError::ShortRead {
+                            offset: $data.get_pos(),
+                            needed: size,
+                            available: $data.remaining(),
+                        },
+                    );
+                    break;
+                }
+
+                let item = $read;
+                if $data.nonfinite_float().is_some() {
+                    break; // Reported once the lump is done
+                }
+
+                // Hands the data read to the visitor
+                $visitor.$callback(item);
+                count += 1;
             }
         }};
+        ($data:expr, $limit:expr, $visitor:ident.$callback:ident, $kind:ty) => {
+            parse_type!(
+                $data,
+                $limit,
+                $visitor.$callback,
+                size<$kind>::MIN_SIZE,
+                read<$kind>::from_reader(&mut $data)
+            )
+        };
+    }
+
+    /// How many times its compressed size a block's buffer is allocated at before decoding.
+    /// LZMA rarely gets past this on map data, and a crafted header can't claim more.
+    const MAX_PREALLOC_RATIO: usize = 16;
+
+    /// Decompresses a whole lump, up to the default size limit
+    pub(crate) fn decompress_lump(bytes: &[u8]) -> Result<Vec<u8>, BspError> {
+        decompress_block(bytes, DEFAULT_MAX_DECOMPRESSED_SIZE)
     }
 
-    fn decompress_lumps(mut data: LumpReader) -> LumpReader {
-        let _ = data.read_u32(); // id
-        let actual_size = data.read_u32();
-        let _ = data.read_u32(); // lzma_size
-        let properties = [
-            data.read_u8(),
-            data.read_u8(),
-            data.read_u8(),
-            data.read_u8(),
-            data.read_u8(),
-        ];
+    /// Decompresses a block in Valve's LZMA format, used for compressed lumps and game lumps.
+    /// It's stored as a normal LZMA stream with a different header, so the header is rewritten.
+    /// Blocks claiming to be larger than max_size are rejected.
+    pub(crate) fn decompress_block(bytes: &[u8], max_size: usize) -> Result<Vec<u8>, BspError> {
+        // Valve's header is the id, both sizes and the 5 bytes of LZMA properties
+        if bytes.len() < 17 {
+            return Err(BspError::Decompression(format!(
+                "lump is only {} bytes, too short for an LZMA header",
+                bytes.len()
+            )));
+        }
+
+        if &bytes[0..4] != b"LZMA" {
+            return Err(BspError::Decompression(
+                "compressed lump is missing the LZMA header".to_string(),
+            ));
+        }
+
+        // The LZMA size is left out as the stream ends itself
+        let actual_size = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        let properties = &bytes[12..17];
+        if actual_size as usize > max_size {
+            return Err(BspError::DecompressedTooLarge {
+                size: actual_size as usize,
+                limit: max_size,
+            });
+        }
 
-        let mut out = vec![0; actual_size as usize];
+        // The size comes from the file, so it isn't trusted for the allocation up front.
+        // The buffer grows past this as the data decodes if the block really is that large.
+        let mut out =
+            Vec::with_capacity((actual_size as usize).min(bytes.len() * MAX_PREALLOC_RATIO));
 
         let data_in = [
-            &properties as &[u8],
+            properties,
             &(actual_size as u64).to_le_bytes(),
-            &data.get_data()[17..],
+            &bytes[17..],
         ]
         .concat();
 
-        lzma_rs::lzma_decompress(&mut std::io::Cursor::new(data_in), &mut out).unwrap();
+        lzma_rs::lzma_decompress(&mut std::io::Cursor::new(data_in), &mut out)
+            .map_err(|e| BspError::Decompression(e.to_string()))?;
 
-        LumpReader::new(&out)
+        Ok(out)
     }
 
-    pub fn parse_lump_data(lumps: Vec<Lump>, full_data: &[u8]) -> ParsedLumps {
+    pub fn parse_lump_data<L: AsRef<[Lump]>>(lumps: L, full_data: &[u8]) -> ParsedLumps {
+        parse_lump_data_with_options(lumps, full_data, &ParseOptions::default())
+    }
+
+    pub fn parse_lump_data_with_options<L: AsRef<[Lump]>>(
+        lumps: L,
+        full_data: &[u8],
+        options: &ParseOptions,
+    ) -> ParsedLumps {
         // Creates ParsedLumps empty and ready to be filled.
         let mut parsed: ParsedLumps = Default::default();
 
+        parse_lump_data_visit(lumps, full_data, options, &mut parsed);
+
+        parsed
+    }
+
+    /// Returns a lump's bytes, or None if its directory entry points outside of the file
+    pub(crate) fn lump_bytes<'a>(lump: &Lump, full_data: &'a [u8]) -> Option<&'a [u8]> {
+        if lump.fileofs < 0 || lump.filelen < 0 {
+            return None;
+        }
+
+        let start = lump.fileofs as usize;
+        full_data.get(start..start + lump.filelen as usize)
+    }
+
+    /// Parses the lumps, handing each item to the visitor instead of collecting them
+    pub fn parse_lump_data_visit<L: AsRef<[Lump]>, V: LumpVisitor>(
+        lumps: L,
+        full_data: &[u8],
+        options: &ParseOptions,
+        visitor: &mut V,
+    ) {
+        parse_lumps(lumps.as_ref(), full_data, options, visitor, |_, _| {});
+    }
+
+    /// The loop behind every parse_lump_data function, calling after_lump once each lump
+    /// has been handed to the visitor
+    fn parse_lumps<V: LumpVisitor, F: FnMut(usize, &Lump)>(
+        lumps: &[Lump],
+        full_data: &[u8],
+        options: &ParseOptions,
+        visitor: &mut V,
+        mut after_lump: F,
+    ) {
+        let map = MapInfo::of(full_data);
+        visitor.on_version(map.version);
+        visitor.on_endianness(map.endianness);
+
         for (i, lump) in lumps.iter().enumerate() {
-            if lump.fileofs == 0 {
-                continue; // Lump isn't actually included
+            parse_lump(i, lump, full_data, map, options, visitor);
+            after_lump(i, lump);
+        }
+    }
+
+    /// What the header says about how the lumps are laid out
+    #[derive(Clone, Copy)]
+    struct MapInfo {
+        version: i32,
+        endianness: Endianness,
+    }
+
+    impl MapInfo {
+        /// Reads the header, with version 0 if the data is too short to have one.
+        /// The magic has already been checked by BspParser::fetch_header.
+        fn of(full_data: &[u8]) -> Self {
+            let endianness = Endianness::of_map(full_data).unwrap_or_default();
+            let mut header = LumpReader::new(full_data.get(4..8).unwrap_or(&[]));
+            header.set_endianness(endianness);
+
+            MapInfo {
+                version: header.try_read_i32().unwrap_or(0),
+                endianness,
             }
+        }
+    }
 
-            let mut data = LumpReader::new(
-                &full_data[lump.fileofs as usize..(lump.fileofs + lump.filelen) as usize],
-            );
+    /// Parses the lumps like parse_lump_data_with_options, calling on_progress after each lump
+    /// with its type and the fraction of the map's lump bytes parsed so far
+    pub fn parse_lump_data_with_progress<L: AsRef<[Lump]>, F: FnMut(LumpType, f32)>(
+        lumps: L,
+        full_data: &[u8],
+        options: &ParseOptions,
+        mut on_progress: F,
+    ) -> ParsedLumps {
+        let lumps = lumps.as_ref();
+        let mut parsed: ParsedLumps = Default::default();
 
-            if lump.ident != [0, 0, 0, 0] {
-                // The packet is compressed. Read the header, convert to normal LZMA and decompress
-                data = decompress_lumps(data);
+        let size = |lump: &Lump| {
+            if lump.fileofs != 0 {
+                lump.filelen.max(0) as u64
+            } else {
+                0
             }
+        };
+        let total: u64 = lumps.iter().map(size).sum();
+        let mut done = 0;
 
-            match i {
-                i if i == LumpType::Entities as usize => {
-                    lazy_static! {
-                    // Regex to match the contents within currly brackets
-                                static ref BLOCK_RE: Regex = Regex::new("(\\{(?:.|\n)+?\\})").unwrap();
-                    // Regex to get the key:value pairs out of the data
-                                static ref ITEM_RE: Regex = Regex::new("\"(.+?)\" \"(.+?)\"\n").unwrap();
-                            }
+        parse_lumps(lumps, full_data, options, &mut parsed, |i, lump| {
+            done += size(lump);
+            if let Some(lump_type) = LumpType::from_index(i) {
+                on_progress(
+                    lump_type,
+                    if total == 0 {
+                        1.0
+                    } else {
+                        done as f32 / total as f32
+                    },
+                );
+            }
+        });
 
-                    let data = String::from_utf8_lossy(data.get_data());
+        parsed
+    }
 
-                    for capture in BLOCK_RE.captures_iter(&data) {
-                        let mut map = HashMap::new();
+    /// Parses the lump at index i of the directory into the visitor
+    fn parse_lump<V: LumpVisitor>(
+        i: usize,
+        lump: &Lump,
+        full_data: &[u8],
+        map: MapInfo,
+        options: &ParseOptions,
+        visitor: &mut V,
+    ) {
+        let present = lump.fileofs != 0;
+        let compressed = present && lump.is_compressed();
+        let raw = if present {
+            lump_bytes(lump, full_data)
+        } else {
+            Some(&[][..])
+        };
 
-                        // Loop through individual entity sections
-                        for capture in ITEM_RE.captures_iter(&capture[0]) {
-                            // Take the key:value pair and put it in a hashmap
-                            map.insert(capture[1].to_string(), capture[2].to_string());
-                        }
-                        parsed.entities.push(map);
-                    }
-                }
-                i if i == LumpType::Plane as usize => parse_type!(data, parsed.planes, Plane),
-                i if i == LumpType::Texdata as usize => parse_type!(data, parsed.texdata, TexData),
-                i if i == LumpType::Vertexes as usize => {
-                    parse_type!(data, parsed.vertex_list, Vertex)
-                }
-                i if i == LumpType::Visibility as usize => (), // This one will be a challenge
-                i if i == LumpType::Nodes as usize => parse_type!(data, parsed.nodes, Node),
-                i if i == LumpType::Texinfo as usize => parse_type!(data, parsed.texinfo, TexInfo),
-                i if i == LumpType::Faces as usize => parse_type!(data, parsed.faces, Face),
-                i if i == LumpType::Lighting as usize => {
-                    parse_type!(data, parsed.lightmap_samples, LightmapSample)
-                }
-                i if i == LumpType::Occlusion as usize => {
-                    parse_type!(data, parsed.occluders, Occluder)
-                }
-                i if i == LumpType::Leafs as usize => (),
-                i if i == LumpType::Faceids as usize => (),
-                i if i == LumpType::Edges as usize => parse_type!(data, parsed.edges, Edge),
-                i if i == LumpType::Surfedges as usize => {
-                    while data.get_pos() < data.get_len() {
-                        parsed.surfedges.push(data.read_i32())
-                    }
+        visitor.on_lump_meta(
+            i,
+            LumpMeta {
+                present,
+                version: lump.version,
+                compressed,
+                length: if !present {
+                    0
+                } else {
+                    lump.uncompressed_size()
+                        .map_or(lump.filelen.max(0) as usize, |size| size as usize)
+                },
+                compressed_data: match raw {
+                    Some(raw) if compressed && options.keep_compressed => Some(raw.to_vec()),
+                    _ => None,
+                },
+            },
+        );
+
+        if !present {
+            return; // Lump isn't actually included
+        }
+
+        let raw = match raw {
+            Some(raw) => raw,
+            None => {
+                visitor.on_lump_error(
+                    i,
+                    BspError::LumpOutOfBounds {
+                        lump: i,
+                        offset: lump.fileofs,
+                        length: lump.filelen,
+                        file_len: full_data.len(),
+                    },
+                );
+                return;
+            }
+        };
+
+        let decompressed;
+        let mut data = LumpReader::new(raw);
+
+        if compressed {
+            // The packet is compressed. Read the header, convert to normal LZMA and decompress
+            decompressed = match decompress_block(raw, options.limits.max_decompressed_size) {
+                Ok(decompressed) => decompressed,
+                Err(e) => {
+                    // Keep going so one damaged lump doesn't lose the rest of the map
+                    visitor.on_lump_error(i, e);
+                    return;
                 }
-                i if i == LumpType::Models as usize => parse_type!(data, parsed.models, Model),
-                i if i == LumpType::Worldlights as usize => (),
-                i if i == LumpType::Leaffaces as usize => {
-                    while data.get_pos() < data.get_len() {
-                        parsed.leaf_faces.push(data.read_u16())
-                    }
+            };
+            data = LumpReader::new(&decompressed);
+        }
+
+        data.set_endianness(map.endianness);
+        data.set_reject_nonfinite(options.reject_nonfinite_floats);
+        let limit = ElementLimit {
+            lump: i,
+            max: options.limits.max_lump_elements,
+        };
+
+        match LumpType::from_index(i) {
+            Some(LumpType::Entities) => {
+                let data = decode_entities_lump(data.get_data());
+                for entity in parse_raw_entities(&data) {
+                    visitor.on_entity(entity.as_map());
+                    visitor.on_raw_entity(entity)
                 }
-                i if i == LumpType::Leafbrushes as usize => {
-                    while data.get_pos() < data.get_len() {
-                        parsed.leaf_brushes.push(data.read_u16())
+            }
+            Some(LumpType::Plane) => parse_type!(data, limit, visitor.on_plane, Plane),
+            Some(LumpType::Texdata) => {
+                parse_type!(data, limit, visitor.on_texdata, TexData)
+            }
+            Some(LumpType::Vertexes) => {
+                parse_type!(data, limit, visitor.on_vertex, Vertex)
+            }
+            Some(LumpType::Visibility) => visitor.on_visibility(Visibility::from_reader(&mut data)),
+            Some(LumpType::Nodes) => parse_type!(data, limit, visitor.on_node, Node),
+            Some(LumpType::Texinfo) => {
+                parse_type!(data, limit, visitor.on_texinfo, TexInfo)
+            }
+            Some(LumpType::Faces) => parse_type!(data, limit, visitor.on_face, Face),
+            Some(LumpType::Lighting) => {
+                parse_type!(data, limit, visitor.on_lightmap_sample, LightmapSample)
+            }
+            Some(LumpType::Occlusion) => {
+                parse_type!(data, limit, visitor.on_occluder, Occluder)
+            }
+            Some(LumpType::Leafs) => {
+                let version = Leaf::layout_version(map.version, lump.version);
+                parse_type!(data, limit, visitor.on_leaf, size Leaf::size(version), read Leaf::from_reader_versioned(&mut data, version))
+            }
+            Some(LumpType::Faceids) => (),
+            Some(LumpType::Edges) => parse_type!(data, limit, visitor.on_edge, Edge),
+            Some(LumpType::Surfedges) => {
+                parse_type!(data, limit, visitor.on_surfedge, size 4, read data.read_i32())
+            }
+            Some(LumpType::Models) => parse_type!(data, limit, visitor.on_model, Model),
+            Some(LumpType::Worldlights) => {
+                parse_type!(data, limit, visitor.on_worldlight, size WorldLight::size(lump.version), read WorldLight::from_reader_versioned(&mut data, lump.version))
+            }
+            Some(LumpType::Leaffaces) => {
+                parse_type!(data, limit, visitor.on_leaf_face, size 2, read data.read_u16())
+            }
+            Some(LumpType::Leafbrushes) => {
+                parse_type!(data, limit, visitor.on_leaf_brush, size 2, read data.read_u16())
+            }
+            Some(LumpType::Brushes) => parse_type!(data, limit, visitor.on_brush, Brush),
+            Some(LumpType::Brushsides) => {
+                parse_type!(data, limit, visitor.on_brushside, Brushside)
+            }
+            Some(LumpType::Areas) => parse_type!(data, limit, visitor.on_area, Area),
+            Some(LumpType::Areaportals) => {
+                parse_type!(data, limit, visitor.on_area_portal, AreaPortal)
+            }
+            Some(LumpType::UNUSED0) => (),
+            Some(LumpType::UNUSED1) => (),
+            Some(LumpType::UNUSED2) => (),
+            Some(LumpType::UNUSED3) => (),
+            Some(LumpType::Dispinfo) => {
+                parse_type!(data, limit, visitor.on_displacement_info, DisplacementInfo)
+            }
+            Some(LumpType::Originalfaces) => {
+                parse_type!(data, limit, visitor.on_original_face, Face)
+            }
+            Some(LumpType::Physdisp) => (), // Needs work on finding the structure
+            Some(LumpType::Physcollide) => {
+                parse_type!(data, limit, visitor.on_physics_model, PhysicsModel)
+            }
+            Some(LumpType::Vertnormals) => (),
+            Some(LumpType::Vertnormalindices) => (),
+            Some(LumpType::DispLightmapAlphas) => visitor.on_disp_lightmap_alphas(data.get_data()),
+            Some(LumpType::DispVerts) => {
+                parse_type!(data, limit, visitor.on_disp_vert, DispVert)
+            }
+            Some(LumpType::DispLightmapSamplePositions) => {
+                visitor.on_disp_lightmap_sample_positions(data.get_data())
+            }
+            Some(LumpType::GameLump) => {
+                let lump_end = lump.fileofs as usize + lump.filelen as usize;
+                for game_lump in parse_game_lumps(
+                    &mut data,
+                    full_data,
+                    lump_end,
+                    options.limits.max_decompressed_size,
+                ) {
+                    match game_lump {
+                        Ok(game_lump) => visitor.on_game_lump(game_lump),
+                        Err(e) => visitor.on_lump_error(i, e),
                     }
                 }
-                i if i == LumpType::Brushes as usize => parse_type!(data, parsed.brushes, Brush),
-                i if i == LumpType::Brushsides as usize => {
-                    parse_type!(data, parsed.brushsides, Brushside)
-                }
-                i if i == LumpType::Areas as usize => parse_type!(data, parsed.areas, Area),
-                i if i == LumpType::Areaportals as usize => {
-                    parse_type!(data, parsed.area_portals, AreaPortal)
-                }
-                i if i == LumpType::UNUSED0 as usize => (),
-                i if i == LumpType::UNUSED1 as usize => (),
-                i if i == LumpType::UNUSED2 as usize => (),
-                i if i == LumpType::UNUSED3 as usize => (),
-                i if i == LumpType::Dispinfo as usize => {
-                    parse_type!(data, parsed.displacement_info, DisplacementInfo)
-                }
-                i if i == LumpType::Originalfaces as usize => {
-                    parse_type!(data, parsed.original_faces, Face)
-                }
-                i if i == LumpType::Physdisp as usize => (), // Needs work on finding the structure
-                i if i == LumpType::Physcollide as usize => {
-                    parse_type!(data, parsed.physics_models, PhysicsModel)
-                }
-                i if i == LumpType::Vertnormals as usize => (),
-                i if i == LumpType::Vertnormalindices as usize => (),
-                i if i == LumpType::DispLightmapAlphas as usize => (),
-                i if i == LumpType::DispVerts as usize => (),
-                i if i == LumpType::DispLightmapSamplePositions as usize => (),
-                i if i == LumpType::GameLump as usize => (),
-                i if i == LumpType::Leafwaterdata as usize => (),
-                i if i == LumpType::Primitives as usize => (),
-                i if i == LumpType::Primverts as usize => (),
-                i if i == LumpType::Primindicies as usize => (),
-                i if i == LumpType::Pakfile as usize => (),
-                i if i == LumpType::Clipportalverts as usize => (),
-                i if i == LumpType::Cubemaps as usize => (),
-                i if i == LumpType::TexdataStringData as usize => (),
-                i if i == LumpType::TexdataStringTable as usize => (),
-                i if i == LumpType::Overlays as usize => (),
-                i if i == LumpType::Leafmindisttowater as usize => (),
-                i if i == LumpType::FaceMacroTextureInfo as usize => (),
-                i if i == LumpType::DispTris as usize => (),
-                i if i == LumpType::Physcollidesurface as usize => (),
-                i if i == LumpType::Wateroverlays as usize => (),
-                i if i == LumpType::LeafAmbientIndexHDR as usize => (),
-                i if i == LumpType::LeafAmbientIndex as usize => (),
-                i if i == LumpType::LightingHDR as usize => (),
-                i if i == LumpType::WorldlightsHDR as usize => (),
-                i if i == LumpType::LeafAmbientLightingHDR as usize => (),
-                i if i == LumpType::LeafAmbientLighting as usize => (),
-                i if i == LumpType::Xzippakfile as usize => (),
-                i if i == LumpType::FacesHDR as usize => (),
-                i if i == LumpType::MapFlags as usize => (),
-                i if i == LumpType::OverlayFades as usize => (),
-                i if i == LumpType::OverlaySystemLevels as usize => (),
-                i if i == LumpType::Physlevel as usize => (),
-                i if i == LumpType::DispMultiblend as usize => (),
-                _ => (),
             }
+            Some(LumpType::Leafwaterdata) => (),
+            Some(LumpType::Primitives) => (),
+            Some(LumpType::Primverts) => (),
+            Some(LumpType::Primindicies) => (),
+            Some(LumpType::Pakfile) => visitor.on_pakfile(data.get_data()),
+            Some(LumpType::Clipportalverts) => (),
+            Some(LumpType::Cubemaps) => parse_type!(data, limit, visitor.on_cubemap, Cubemap),
+            Some(LumpType::TexdataStringData) => visitor.on_texdata_string_data(data.get_data()),
+            Some(LumpType::TexdataStringTable) => {
+                parse_type!(data, limit, visitor.on_texdata_string_table_entry, size 4, read data.read_i32())
+            }
+            Some(LumpType::Overlays) => {
+                parse_type!(data, limit, visitor.on_overlay, Overlay)
+            }
+            Some(LumpType::Leafmindisttowater) => (),
+            Some(LumpType::FaceMacroTextureInfo) => (),
+            Some(LumpType::DispTris) => (),
+            Some(LumpType::Physcollidesurface) => (),
+            Some(LumpType::Wateroverlays) => (),
+            Some(LumpType::LeafAmbientIndexHDR) => {
+                parse_type!(
+                    data,
+                    limit,
+                    visitor.on_leaf_ambient_index_hdr,
+                    LeafAmbientIndex
+                )
+            }
+            Some(LumpType::LeafAmbientIndex) => {
+                parse_type!(data, limit, visitor.on_leaf_ambient_index, LeafAmbientIndex)
+            }
+            Some(LumpType::LightingHDR) => (),
+            Some(LumpType::WorldlightsHDR) => {
+                parse_type!(data, limit, visitor.on_worldlight_hdr, size WorldLight::size(lump.version), read WorldLight::from_reader_versioned(&mut data, lump.version))
+            }
+            Some(LumpType::LeafAmbientLightingHDR) => {
+                parse_type!(
+                    data,
+                    limit,
+                    visitor.on_leaf_ambient_lighting_hdr,
+                    LeafAmbientLighting
+                )
+            }
+            Some(LumpType::LeafAmbientLighting) => {
+                parse_type!(
+                    data,
+                    limit,
+                    visitor.on_leaf_ambient_lighting,
+                    LeafAmbientLighting
+                )
+            }
+            Some(LumpType::Xzippakfile) => (),
+            Some(LumpType::FacesHDR) => (),
+            Some(LumpType::MapFlags) => (),
+            Some(LumpType::OverlayFades) => (),
+            Some(LumpType::OverlaySystemLevels) => (),
+            Some(LumpType::Physlevel) => (),
+            Some(LumpType::DispMultiblend) => (),
+            None => (), // Past the 64 lumps of the directory
         }
 
-        parsed
+        if let Some(offset) = data.nonfinite_float() {
+            visitor.on_lump_error(i, BspError::NonFiniteFloat { lump: i, offset });
+        }
     }
 }
+
+#[test]
+fn test_truncated_compressed_lump() {
+    let entities = b"{\n\"classname\" \"worldspawn\"\n}\n\0";
+    let planes: Vec<u8> = (0..20u32 * 8)
+        .flat_map(|i| i.to_le_bytes().to_vec())
+        .collect();
+
+    let mut compressed = vec![];
+    lzma_rs::lzma_compress(&mut &planes[..], &mut compressed).unwrap();
+
+    // Swap the standard LZMA header (properties + u64 size) for Valve's
+    let mut planes_lump = b"LZMA".to_vec();
+    planes_lump.extend(&(planes.len() as u32).to_le_bytes());
+    planes_lump.extend(&(compressed.len() as u32 - 13).to_le_bytes());
+    planes_lump.extend(&compressed[..5]);
+    planes_lump.extend(&compressed[13..]);
+    planes_lump.truncate(planes_lump.len() / 2);
+
+    let mut data = vec![0; 16];
+    let mut lumps: Vec<Lump> = (0..64)
+        .map(|_| Lump {
+            fileofs: 0,
+            filelen: 0,
+            version: 0,
+            ident: [0; 4],
+        })
+        .collect();
+
+    lumps[LumpType::Entities as usize].fileofs = data.len() as i32;
+    lumps[LumpType::Entities as usize].filelen = entities.len() as i32;
+    data.extend(&entities[..]);
+
+    lumps[LumpType::Plane as usize].fileofs = data.len() as i32;
+    lumps[LumpType::Plane as usize].filelen = planes_lump.len() as i32;
+    lumps[LumpType::Plane as usize].ident = (planes.len() as u32).to_le_bytes();
+    data.extend(&planes_lump);
+
+    let parsed = LumpParser::parse_lump_data(lumps, &data);
+
+    assert_eq!(parsed.entities.len(), 1);
+    assert_eq!(parsed.entities[0]["classname"], "worldspawn");
+    assert!(parsed.planes.is_empty());
+    assert_eq!(parsed.lump_errors.len(), 1);
+    assert_eq!(parsed.lump_errors[0].0, LumpType::Plane as usize);
+    assert_eq!(
+        parsed.present_lumps(),
+        [LumpType::Entities, LumpType::Plane]
+    );
+    assert!(!parsed.has_lump(LumpType::Faces));
+}
+
+#[test]
+fn test_checked_slice() {
+    let items = [1, 2, 3, 4];
+
+    assert_eq!(checked_slice(&items, 1, 2).unwrap(), [2, 3]);
+    assert_eq!(checked_slice(&items, 4, 0).unwrap(), [] as [i32; 0]);
+    assert!(matches!(
+        checked_slice(&items, 3, 2),
+        Err(BspError::SliceOutOfRange {
+            first: 3,
+            count: 2,
+            len: 4
+        })
+    ));
+    assert!(checked_slice(&items, -1, 1).is_err());
+
+    assert_eq!(clamped_slice(&items, 3, 2), [4]);
+    assert_eq!(clamped_slice(&items, 9, 2), [] as [i32; 0]);
+    assert_eq!(clamped_slice(&items, 0, -1), [] as [i32; 0]);
+}
+
+#[test]
+fn test_read_vectors() {
+    let mut bytes = vec![];
+    for f in [1.0f32, -2.0, 3.5, 0.25, 0.5, 0.75, 1.0] {
+        bytes.extend_from_slice(&f.to_le_bytes());
+    }
+    for i in [-1i16, 2, 300] {
+        bytes.extend_from_slice(&i.to_le_bytes());
+    }
+
+    let mut data = LumpReader::new(&bytes);
+    assert_eq!(data.read_vec3(), (1.0, -2.0, 3.5));
+    assert_eq!(data.read_vec4f32(), [0.25, 0.5, 0.75, 1.0]);
+    assert_eq!(data.read_vec3i16(), [-1, 2, 300]);
+    assert!(data.at_end());
+}
+
+#[test]
+fn test_try_read() {
+    let mut data = LumpReader::new(&[1, 0, 0, 0, 0xff, 0xff]);
+
+    assert_eq!(data.try_read_i32().unwrap(), 1);
+    assert!(matches!(
+        data.try_read_u32(),
+        Err(BspError::ShortRead {
+            offset: 4,
+            needed: 4,
+            available: 2
+        })
+    ));
+    // The failed read didn't move the reader
+    assert_eq!(data.try_read_i16().unwrap(), -1);
+    assert!(data.try_read_u8().is_err());
+}
+
+#[test]
+fn test_read_array() {
+    let bytes: Vec<u8> = (1..=3i32).flat_map(|i| i.to_le_bytes()).collect();
+
+    let mut data = LumpReader::new(&bytes);
+    assert!(matches!(
+        data.try_read_array::<i32>(4),
+        Err(BspError::ShortRead {
+            offset: 0,
+            needed: 16,
+            available: 12
+        })
+    ));
+    assert_eq!(data.try_read_array::<u16>(2).unwrap(), [1, 0]);
+    // A count past the end only reads what's there
+    assert_eq!(data.read_array::<i32>(usize::MAX), [2, 3]);
+    assert!(data.at_end());
+}
+
+#[test]
+fn test_lump_compression() {
+    let lump = |ident| Lump {
+        fileofs: 1036,
+        filelen: 64,
+        version: 0,
+        ident,
+    };
+
+    assert!(!lump([0; 4]).is_compressed());
+    assert_eq!(lump([0; 4]).uncompressed_size(), None);
+    assert!(lump(200u32.to_le_bytes()).is_compressed());
+    assert_eq!(lump(200u32.to_le_bytes()).uncompressed_size(), Some(200));
+
+    // A non-zero ident without the LZMA header can't be decompressed
+    assert!(matches!(
+        LumpParser::decompress_block(&[0; 32], usize::MAX),
+        Err(BspError::Decompression(_))
+    ));
+
+    // A header claiming 4 GiB fails on the data rather than allocating that much up front
+    let mut block = b"LZMA".to_vec();
+    block.extend_from_slice(&u32::MAX.to_le_bytes());
+    block.extend_from_slice(&0u32.to_le_bytes());
+    block.extend_from_slice(&[0x5d, 0, 0, 1, 0, 0xff, 0xff]);
+    assert!(matches!(
+        LumpParser::decompress_block(&block, usize::MAX),
+        Err(BspError::Decompression(_))
+    ));
+}
+
+#[test]
+fn test_texture_names() {
+    use crate::fixture::FixtureBuilder;
+    use crate::BspParser;
+
+    let mut texdata = vec![];
+    for index in [1i32, 0] {
+        texdata.extend([0.5f32; 3].iter().flat_map(|f| f.to_le_bytes()));
+        for value in [index, 64, 64, 64, 64] {
+            texdata.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    let string_data = b"DEV/DEV_MEASUREWALL01A\0TOOLS/TOOLSNODRAW\0".to_vec();
+    let string_table = [0i32, 23].iter().flat_map(|i| i.to_le_bytes()).collect();
+
+    let parser = BspParser {
+        data: FixtureBuilder::new()
+            .lump(LumpType::Texdata, texdata)
+            .lump(LumpType::TexdataStringData, string_data)
+            .lump(LumpType::TexdataStringTable, string_table)
+            .build()
+            .into(),
+    };
+    let parsed = LumpParser::parse_lump_data(parser.read_lump_info().unwrap(), &parser.data);
+
+    assert_eq!(
+        parsed.texture_name(&parsed.texdata[0]),
+        Some("TOOLS/TOOLSNODRAW")
+    );
+    assert_eq!(
+        parsed.texture_name(&parsed.texdata[1]),
+        Some("DEV/DEV_MEASUREWALL01A")
+    );
+
+    let missing = TexData {
+        texdata_string_table_index: 2,
+        ..parsed.texdata[0].clone()
+    };
+    assert_eq!(parsed.texture_name(&missing), None);
+}
+
+#[test]
+fn test_face_vertices_winding() {
+    use LumpParser::ParsedLumps;
+
+    let vertex = |x, y| Vertex { x, y, z: 0.0 };
+    let face = crate::fixture::face(1, 3);
+    let lumps = ParsedLumps {
+        vertex_list: vec![vertex(0.0, 0.0), vertex(64.0, 0.0), vertex(0.0, 64.0)],
+        edges: [[0, 0], [0, 1], [2, 1], [2, 0]]
+            .iter()
+            .map(|&vertex_indicies| Edge { vertex_indicies })
+            .collect(),
+        // Edge 2 is stored from the third vertex to the second, so its surfedge is negative
+        // and it's walked backwards
+        surfedges: vec![0, 1, -2, 3],
+        models: vec![Model {
+            mins: (0.0, 0.0, 0.0),
+            maxs: (0.0, 0.0, 0.0),
+            origin: (0.0, 0.0, 0.0),
+            head_node: 0,
+            first_face: 0,
+            num_faces: 1,
+        }],
+        faces: vec![face.clone()],
+        ..Default::default()
+    };
+
+    let loop_points: Vec<(f32, f32)> = lumps
+        .face_vertices(&face)
+        .iter()
+        .map(|v| (v.x, v.y))
+        .collect();
+    assert_eq!(loop_points, [(0.0, 0.0), (64.0, 0.0), (0.0, 64.0)]);
+
+    let world: Vec<_> = lumps.world_faces().collect();
+    assert_eq!(world.len(), 1);
+    assert_eq!(world[0].1.len(), 3);
+}
+
+#[test]
+fn test_lump_type_index() {
+    for i in 0..crate::LUMP_COUNT {
+        assert_eq!(LumpType::from_index(i).unwrap().index(), i);
+    }
+    assert_eq!(LumpType::from_index(crate::LUMP_COUNT), None);
+}
+
+#[test]
+fn test_parse_with_progress() {
+    use crate::fixture::FixtureBuilder;
+    use crate::BspParser;
+
+    let parser = BspParser {
+        data: FixtureBuilder::new()
+            .entities("{\n\"classname\" \"worldspawn\"\n}\n")
+            .vertices(&[(0.0, 0.0, 0.0), (64.0, 0.0, 0.0)])
+            .build()
+            .into(),
+    };
+    let lumps = parser.read_lump_info().unwrap();
+
+    let mut calls = vec![];
+    let parsed = LumpParser::parse_lump_data_with_progress(
+        &lumps,
+        &parser.data,
+        &Default::default(),
+        |lump_type, fraction| calls.push((lump_type, fraction)),
+    );
+
+    assert_eq!(calls.len(), crate::LUMP_COUNT);
+    assert_eq!(calls[LumpType::Entities as usize].0, LumpType::Entities);
+    assert!(calls.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    assert_eq!(calls.last().unwrap().1, 1.0);
+
+    let plain = LumpParser::parse_lump_data(&lumps, &parser.data);
+    assert_eq!(parsed.entities, plain.entities);
+    assert_eq!(parsed.vertex_list, plain.vertex_list);
+    assert_eq!(parsed.lump_meta, plain.lump_meta);
+}
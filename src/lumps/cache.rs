@@ -0,0 +1,94 @@
+use crate::lumps::LumpParser::ParsedLumps;
+
+use std::io::{Read, Write};
+
+/// Identifies a cache file written by `ParsedLumps::write_cache`
+const CACHE_MAGIC: [u8; 4] = *b"BSPC";
+/// Bumped whenever a change to the parsed types changes the encoded layout
+const CACHE_VERSION: u32 = 6;
+
+/// Errors that can occur while reading or writing a cache
+#[derive(Debug, thiserror::Error)]
+pub enum CacheError {
+    #[error("cache I/O failed: {0}")]
+    Io(#[from] std::io::Error),
+    /// The cache body couldn't be encoded or decoded
+    #[error("invalid cache data: {0}")]
+    Encoding(#[from] bincode::Error),
+    /// The data doesn't start with the cache magic
+    #[error("not a cache file")]
+    BadMagic,
+    /// The cache was written by a version of the crate with a different layout
+    #[error("cache is format version {found} but expected {expected}")]
+    UnsupportedVersion { found: u32, expected: u32 },
+    /// The map has changed since the cache was written
+    #[error("cache is stale, map CRC is {found:08x} but the cache was built from {cached:08x}")]
+    Stale { found: u32, cached: u32 },
+}
+
+impl ParsedLumps {
+    /// Writes the parsed lumps to a compact binary cache, tagged with the CRC of `map_data`.
+    /// `map_data` should be the bytes the lumps were parsed from, e.g. `BspParser::data`.
+    /// Lump errors aren't stored.
+    pub fn write_cache<W: Write>(&self, mut w: W, map_data: &[u8]) -> Result<(), CacheError> {
+        w.write_all(&CACHE_MAGIC)?;
+        w.write_all(&CACHE_VERSION.to_le_bytes())?;
+        w.write_all(&crc32fast::hash(map_data).to_le_bytes())?;
+        bincode::serialize_into(w, self)?;
+        Ok(())
+    }
+
+    /// Reads lumps written by `write_cache`, rejecting the cache if it was built from different map data
+    pub fn read_cache<R: Read>(mut r: R, map_data: &[u8]) -> Result<ParsedLumps, CacheError> {
+        let mut header = [0; 12];
+        r.read_exact(&mut header)?;
+
+        if header[0..4] != CACHE_MAGIC {
+            return Err(CacheError::BadMagic);
+        }
+
+        let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if version != CACHE_VERSION {
+            return Err(CacheError::UnsupportedVersion {
+                found: version,
+                expected: CACHE_VERSION,
+            });
+        }
+
+        let cached = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+        let found = crc32fast::hash(map_data);
+        if cached != found {
+            return Err(CacheError::Stale { found, cached });
+        }
+
+        Ok(bincode::deserialize_from(r)?)
+    }
+}
+
+#[test]
+fn test_cache_round_trip() {
+    use crate::fixture::FixtureBuilder;
+    use crate::BspParser;
+
+    let data = FixtureBuilder::new()
+        .entities("{\n\"classname\" \"worldspawn\"\n}\n")
+        .vertices(&[(0.0, 0.0, 0.0), (64.0, 0.0, 0.0), (0.0, 64.0, 0.0)])
+        .build();
+    let parser = BspParser { data: data.into() };
+    let lumps =
+        crate::lumps::LumpParser::parse_lump_data(parser.read_lump_info().unwrap(), &parser.data);
+
+    let mut cache = vec![];
+    lumps.write_cache(&mut cache, &parser.data).unwrap();
+
+    let cached = ParsedLumps::read_cache(&cache[..], &parser.data).unwrap();
+    assert_eq!(cached.vertex_list, lumps.vertex_list);
+    assert_eq!(cached.entities, lumps.entities);
+
+    let mut changed = parser.data.to_vec();
+    *changed.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        ParsedLumps::read_cache(&cache[..], &changed),
+        Err(CacheError::Stale { .. })
+    ));
+}
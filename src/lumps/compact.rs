@@ -0,0 +1,251 @@
+use crate::lumps::lump_types::*;
+use crate::lumps::LumpParser::ParsedLumps;
+
+/// Maps each index to its position once the unused elements are removed
+fn remap(used: &[bool]) -> Vec<Option<usize>> {
+    let mut next = 0;
+    used.iter()
+        .map(|&used| {
+            if used {
+                next += 1;
+                Some(next - 1)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Removes the elements that aren't marked as used, keeping the rest in order
+fn retain_used<T>(items: &mut Vec<T>, used: &[bool]) {
+    let mut i = 0;
+    items.retain(|_| {
+        i += 1;
+        used[i - 1]
+    });
+}
+
+/// Looks up where an index moved to. Out of range indices are left for `validate` to report.
+fn moved(map: &[Option<usize>], index: i64) -> Option<usize> {
+    if index < 0 {
+        return None;
+    }
+    map.get(index as usize).copied().flatten()
+}
+
+impl ParsedLumps {
+    /// Removes every entity matching the predicate, such as `info_null` or editor only entities.
+    /// Returns how many were removed.
+    ///
+    /// Brush models of removed entities are left in place, as other entities refer to models
+    /// by index. Removing worldspawn leaves the map unloadable.
+    pub fn remove_entities<F: Fn(&Entity) -> bool>(&mut self, pred: F) -> usize {
+        let keep: Vec<bool> = self.entities.iter().map(|entity| !pred(entity)).collect();
+
+        retain_used(&mut self.entities, &keep);
+        // The raw entities are parsed alongside the entities, so they line up by index
+        if self.raw_entities.len() == keep.len() {
+            retain_used(&mut self.raw_entities, &keep);
+        }
+
+        keep.iter().filter(|&&keep| !keep).count()
+    }
+
+    /// Removes unreferenced edges, vertices and planes, rewriting every index into them.
+    ///
+    /// Edge 0 is always kept since a surfedge can't negate it, and planes are kept in their
+    /// front and back pairs (`plane_num ^ 1`) as the compile tools expect.
+    pub fn compact(&mut self) {
+        // Edges go first, as removing them can leave vertices unreferenced
+        self.compact_edges();
+        self.compact_vertices();
+        self.compact_planes();
+    }
+
+    fn compact_edges(&mut self) {
+        let mut used = vec![false; self.edges.len()];
+        if let Some(first) = used.first_mut() {
+            *first = true;
+        }
+        for surfedge in &self.surfedges {
+            if let Some(used) = used.get_mut(surfedge.unsigned_abs() as usize) {
+                *used = true;
+            }
+        }
+
+        let map = remap(&used);
+        for surfedge in &mut self.surfedges {
+            if let Some(new) = moved(&map, surfedge.unsigned_abs() as i64) {
+                *surfedge = surfedge.signum() * new as i32;
+            }
+        }
+        retain_used(&mut self.edges, &used);
+    }
+
+    fn compact_vertices(&mut self) {
+        let mut used = vec![false; self.vertex_list.len()];
+        let mut mark = |index: i64| {
+            if index >= 0 {
+                if let Some(used) = used.get_mut(index as usize) {
+                    *used = true;
+                }
+            }
+        };
+        for edge in &self.edges {
+            edge.vertex_indicies.iter().for_each(|&v| mark(v as i64));
+        }
+        for occluder in &self.occluders {
+            occluder
+                .vertex_indicies
+                .iter()
+                .for_each(|&v| mark(v as i64));
+        }
+
+        let map = remap(&used);
+        for edge in &mut self.edges {
+            for vertex in &mut edge.vertex_indicies {
+                if let Some(new) = moved(&map, *vertex as i64) {
+                    *vertex = new as u16;
+                }
+            }
+        }
+        for occluder in &mut self.occluders {
+            for vertex in &mut occluder.vertex_indicies {
+                if let Some(new) = moved(&map, *vertex as i64) {
+                    *vertex = new as i32;
+                }
+            }
+        }
+        retain_used(&mut self.vertex_list, &used);
+    }
+
+    fn compact_planes(&mut self) {
+        let mut used = vec![false; self.planes.len()];
+        let mut mark = |index: i64| {
+            if index >= 0 && (index as usize) < used.len() {
+                let pair = index as usize & !1;
+                used[pair] = true;
+                if let Some(back) = used.get_mut(pair + 1) {
+                    *back = true;
+                }
+            }
+        };
+        self.nodes.iter().for_each(|n| mark(n.plane_num as i64));
+        self.faces.iter().for_each(|f| mark(f.plane_num as i64));
+        self.original_faces
+            .iter()
+            .for_each(|f| mark(f.plane_num as i64));
+        self.brushsides
+            .iter()
+            .for_each(|s| mark(s.plane_num as i64));
+        self.area_portals
+            .iter()
+            .for_each(|p| mark(p.plane_num as i64));
+        for occluder in &self.occluders {
+            occluder
+                .poly_data
+                .iter()
+                .for_each(|p| mark(p.plane_num as i64));
+        }
+
+        let map = remap(&used);
+        let update = |plane_num: i64| moved(&map, plane_num);
+        for node in &mut self.nodes {
+            if let Some(new) = update(node.plane_num as i64) {
+                node.plane_num = new as i32;
+            }
+        }
+        for face in self.faces.iter_mut().chain(&mut self.original_faces) {
+            if let Some(new) = update(face.plane_num as i64) {
+                face.plane_num = new as u16;
+            }
+        }
+        for side in &mut self.brushsides {
+            if let Some(new) = update(side.plane_num as i64) {
+                side.plane_num = new as u16;
+            }
+        }
+        for portal in &mut self.area_portals {
+            if let Some(new) = update(portal.plane_num as i64) {
+                portal.plane_num = new as i32;
+            }
+        }
+        for occluder in &mut self.occluders {
+            for poly in &mut occluder.poly_data {
+                if let Some(new) = update(poly.plane_num as i64) {
+                    poly.plane_num = new as i32;
+                }
+            }
+        }
+        retain_used(&mut self.planes, &used);
+    }
+}
+
+#[test]
+fn test_remove_entities() {
+    let text = concat!(
+        "{\n\"classname\" \"worldspawn\"\n}\n",
+        "{\n\"classname\" \"info_null\"\n}\n",
+        "{\n\"classname\" \"light\"\n}\n",
+    );
+    let mut lumps = ParsedLumps {
+        entities: crate::lumps::entities::parse_entities(text),
+        raw_entities: crate::lumps::entities::parse_raw_entities(text),
+        ..Default::default()
+    };
+
+    let removed = lumps.remove_entities(|entity| entity["classname"] == "info_null");
+
+    assert_eq!(removed, 1);
+    assert_eq!(lumps.entities.len(), 2);
+    assert_eq!(lumps.raw_entities[1].get("classname"), Some("light"));
+}
+
+#[test]
+fn test_compact() {
+    let vertex = |x| Vertex { x, y: 0.0, z: 0.0 };
+    let plane = |dist_from_origin| Plane {
+        normal: (1.0, 0.0, 0.0),
+        dist_from_origin,
+        r#type: 0,
+    };
+    let edge = |a, b| Edge {
+        vertex_indicies: [a, b],
+    };
+    let mut lumps = ParsedLumps {
+        vertex_list: (0..5).map(|i| vertex(i as f32)).collect(),
+        // Edge 1 and vertex 1 aren't referenced by any face
+        edges: vec![edge(0, 0), edge(1, 2), edge(2, 3), edge(3, 4), edge(4, 2)],
+        surfedges: vec![2, 3, -4],
+        planes: (0..6).map(|i| plane(i as f32)).collect(),
+        brushsides: vec![Brushside {
+            plane_num: 5,
+            texinfo: 0,
+            dispinfo: -1,
+            bevel: 0,
+        }],
+        ..Default::default()
+    };
+
+    lumps.compact();
+
+    assert_eq!(lumps.edges.len(), 4);
+    assert_eq!(lumps.surfedges, [1, 2, -3]);
+    assert_eq!(lumps.vertex_list.len(), 4);
+    // Each edge still joins the same points
+    let edge_points: Vec<[f32; 2]> = lumps.surfedges[..]
+        .iter()
+        .map(|&s| lumps.edges[s.unsigned_abs() as usize].vertex_indicies)
+        .map(|[a, b]| {
+            [
+                lumps.vertex_list[a as usize].x,
+                lumps.vertex_list[b as usize].x,
+            ]
+        })
+        .collect();
+    assert_eq!(edge_points, [[2.0, 3.0], [3.0, 4.0], [4.0, 2.0]]);
+    // Only plane 5 is used, 4 is kept as its pair
+    assert_eq!(lumps.planes.len(), 2);
+    assert_eq!(lumps.brushsides[0].plane_num, 1);
+    assert_eq!(lumps.planes[1].dist_from_origin, 5.0);
+}
@@ -0,0 +1,128 @@
+//! Converting positions between Source's coordinate system and the Y-up one used by
+//! glTF, OpenGL and most engines.
+//!
+//! Source is right handed with Z up, X forward and Y to the left, measured in Hammer units
+//! of 0.75 inches. Converting to Y-up rotates -90 degrees around X, taking (x, y, z) to
+//! (x, z, -y). That's the same rotation Blender's glTF exporter uses for its own Z-up
+//! scenes, and being a rotation rather than a swap of two axes, it keeps the handedness,
+//! so face winding order and normals stay correct.
+
+use crate::lumps::lump_types::Vector;
+use crate::lumps::math::scale;
+use crate::lumps::LumpParser::ParsedLumps;
+
+/// The length of a Hammer unit in meters
+pub const METERS_PER_UNIT: f32 = 0.01905;
+
+/// Converts Source's Z-up vectors to and from Y-up
+pub trait VectorExt {
+    /// Rotates a Z-up vector to Y-up, keeping its units
+    fn to_gl(self) -> Vector;
+    /// Rotates a Y-up vector back to Z-up, the inverse of to_gl
+    fn to_source(self) -> Vector;
+}
+
+impl VectorExt for Vector {
+    fn to_gl(self) -> Vector {
+        (self.0, self.2, -self.1)
+    }
+
+    fn to_source(self) -> Vector {
+        (self.0, -self.2, self.1)
+    }
+}
+
+/// The axes and units to convert map positions into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoordinateSystem {
+    /// Rotate to Y-up, see the module docs
+    pub y_up: bool,
+    /// Units of the target per Hammer unit
+    pub scale: f32,
+}
+
+impl CoordinateSystem {
+    /// Source's own coordinates, converting with this changes nothing
+    pub const SOURCE: CoordinateSystem = CoordinateSystem {
+        y_up: false,
+        scale: 1.0,
+    };
+
+    /// Y-up in meters, as glTF expects
+    pub const GLTF: CoordinateSystem = CoordinateSystem {
+        y_up: true,
+        scale: METERS_PER_UNIT,
+    };
+
+    /// Converts a position from the map into this system
+    pub fn transform_point(&self, point: Vector) -> Vector {
+        self.transform_normal(scale(point, self.scale))
+    }
+
+    /// Converts a direction such as a normal into this system. Directions are only rotated,
+    /// since scaling would change their length.
+    pub fn transform_normal(&self, normal: Vector) -> Vector {
+        if self.y_up {
+            normal.to_gl()
+        } else {
+            normal
+        }
+    }
+
+    /// Converts a position in this system back into map coordinates, for writing maps
+    pub fn inverse_point(&self, point: Vector) -> Vector {
+        scale(self.inverse_normal(point), 1.0 / self.scale)
+    }
+
+    /// Converts a direction in this system back into map coordinates
+    pub fn inverse_normal(&self, normal: Vector) -> Vector {
+        if self.y_up {
+            normal.to_source()
+        } else {
+            normal
+        }
+    }
+}
+
+impl ParsedLumps {
+    /// Converts every vertex into another coordinate system. The result is in the same
+    /// order as vertex_list, so the edges still index into it.
+    pub fn transform_vertices(&self, system: &CoordinateSystem) -> Vec<Vector> {
+        self.vertex_list
+            .iter()
+            .map(|vertex| system.transform_point((vertex.x, vertex.y, vertex.z)))
+            .collect()
+    }
+
+    /// Returns the vertices rotated to Y-up, still in Hammer units.
+    /// See transform_vertices to convert units as well.
+    pub fn to_y_up(&self) -> Vec<Vector> {
+        self.transform_vertices(&CoordinateSystem {
+            y_up: true,
+            scale: 1.0,
+        })
+    }
+}
+
+#[test]
+fn test_coordinate_system() {
+    use crate::lumps::math::cross;
+
+    // Up becomes up and forward stays forward
+    assert_eq!((0.0, 0.0, 1.0).to_gl(), (0.0, 1.0, 0.0));
+    assert_eq!((1.0, 0.0, 0.0).to_gl(), (1.0, 0.0, 0.0));
+    // Handedness is kept, so X cross Y is still Z
+    let (x, y) = ((1.0, 0.0, 0.0), (0.0, 1.0, 0.0));
+    assert_eq!(cross(x.to_gl(), y.to_gl()), cross(x, y).to_gl());
+
+    let point = (64.0, -128.0, 32.0);
+    assert_eq!(point.to_gl().to_source(), point);
+
+    let system = CoordinateSystem {
+        y_up: true,
+        scale: 0.5,
+    };
+    assert_eq!(system.transform_point(point), (32.0, 16.0, 64.0));
+    assert_eq!(system.inverse_point(system.transform_point(point)), point);
+    assert_eq!(CoordinateSystem::SOURCE.transform_point(point), point);
+}
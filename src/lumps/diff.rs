@@ -0,0 +1,260 @@
+use crate::lumps::lump_types::Entity;
+use crate::lumps::LumpParser::ParsedLumps;
+use crate::lumps::LumpType;
+
+use std::collections::{BTreeSet, HashMap};
+
+/// How many elements of a lump each map has
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CountDiff {
+    pub lump: LumpType,
+    pub before: usize,
+    pub after: usize,
+}
+
+/// How an entity was matched between the two maps
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntityId {
+    /// By a targetname unique to one entity in each map
+    Targetname(String),
+    /// By position among the entities not matched by name, as the index in the first map
+    /// or, for added entities, the second
+    Index(usize),
+}
+
+/// A key whose value differs, None where the entity doesn't have the key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyChange {
+    pub key: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntityChange {
+    Added { classname: Option<String> },
+    Removed { classname: Option<String> },
+    Changed(Vec<KeyChange>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntityDiff {
+    pub id: EntityId,
+    pub change: EntityChange,
+}
+
+/// What changed between two compiles of a map
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MapDiff {
+    /// Lumps with a different number of elements
+    pub counts: Vec<CountDiff>,
+    pub entities: Vec<EntityDiff>,
+}
+
+impl MapDiff {
+    /// True if nothing the diff looks at changed
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty() && self.entities.is_empty()
+    }
+}
+
+/// The element counts compared by diff
+fn lump_counts(lumps: &ParsedLumps) -> [(LumpType, usize); 18] {
+    [
+        (LumpType::Entities, lumps.entities.len()),
+        (LumpType::Plane, lumps.planes.len()),
+        (LumpType::Texdata, lumps.texdata.len()),
+        (LumpType::Vertexes, lumps.vertex_list.len()),
+        (LumpType::Nodes, lumps.nodes.len()),
+        (LumpType::Texinfo, lumps.texinfo.len()),
+        (LumpType::Faces, lumps.faces.len()),
+        (LumpType::Lighting, lumps.lightmap_samples.len()),
+        (LumpType::Leafs, lumps.leaves.len()),
+        (LumpType::Edges, lumps.edges.len()),
+        (LumpType::Surfedges, lumps.surfedges.len()),
+        (LumpType::Models, lumps.models.len()),
+        (LumpType::Brushes, lumps.brushes.len()),
+        (LumpType::Brushsides, lumps.brushsides.len()),
+        (LumpType::Dispinfo, lumps.displacement_info.len()),
+        (LumpType::DispVerts, lumps.disp_verts.len()),
+        (LumpType::Overlays, lumps.overlays.len()),
+        (LumpType::GameLump, lumps.game_lumps.len()),
+    ]
+}
+
+/// Maps each targetname used by exactly one entity to that entity
+fn unique_targetnames(entities: &[Entity]) -> HashMap<&str, usize> {
+    let mut names: HashMap<&str, Option<usize>> = HashMap::new();
+    for (i, entity) in entities.iter().enumerate() {
+        if let Some(name) = entity.get("targetname") {
+            names
+                .entry(name)
+                .and_modify(|index| *index = None)
+                .or_insert(Some(i));
+        }
+    }
+
+    names
+        .into_iter()
+        .filter_map(|(name, index)| Some((name, index?)))
+        .collect()
+}
+
+fn key_changes(before: &Entity, after: &Entity) -> Vec<KeyChange> {
+    let keys: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+
+    keys.into_iter()
+        .filter(|key| before.get(*key) != after.get(*key))
+        .map(|key| KeyChange {
+            key: key.clone(),
+            before: before.get(key).cloned(),
+            after: after.get(key).cloned(),
+        })
+        .collect()
+}
+
+impl ParsedLumps {
+    /// Compares this map with another compile of it, reporting lumps whose element counts
+    /// changed and entities that were added, removed or had keys changed.
+    ///
+    /// Entities with a targetname unique in both maps are matched by it, the rest are
+    /// matched in order. This isn't a structural diff of the geometry.
+    pub fn diff(&self, other: &ParsedLumps) -> MapDiff {
+        let counts = lump_counts(self)
+            .iter()
+            .zip(lump_counts(other).iter())
+            .filter(|(before, after)| before.1 != after.1)
+            .map(|(before, after)| CountDiff {
+                lump: before.0,
+                before: before.1,
+                after: after.1,
+            })
+            .collect();
+
+        let mut entities = vec![];
+        let mut matched_before = vec![false; self.entities.len()];
+        let mut matched_after = vec![false; other.entities.len()];
+
+        let names_after = unique_targetnames(&other.entities);
+        let mut names_before: Vec<(&str, usize)> =
+            unique_targetnames(&self.entities).into_iter().collect();
+        names_before.sort_by_key(|&(_, i)| i);
+
+        for (name, i) in names_before {
+            if let Some(&j) = names_after.get(name) {
+                matched_before[i] = true;
+                matched_after[j] = true;
+
+                let changes = key_changes(&self.entities[i], &other.entities[j]);
+                if !changes.is_empty() {
+                    entities.push(EntityDiff {
+                        id: EntityId::Targetname(name.to_string()),
+                        change: EntityChange::Changed(changes),
+                    });
+                }
+            }
+        }
+
+        let unmatched = |matched: &[bool]| -> Vec<usize> {
+            (0..matched.len()).filter(|&i| !matched[i]).collect()
+        };
+        let rest_before = unmatched(&matched_before);
+        let rest_after = unmatched(&matched_after);
+        let classname = |entity: &Entity| entity.get("classname").cloned();
+
+        for (&i, &j) in rest_before.iter().zip(&rest_after) {
+            let changes = key_changes(&self.entities[i], &other.entities[j]);
+            if !changes.is_empty() {
+                entities.push(EntityDiff {
+                    id: EntityId::Index(i),
+                    change: EntityChange::Changed(changes),
+                });
+            }
+        }
+        for &i in rest_before.iter().skip(rest_after.len()) {
+            entities.push(EntityDiff {
+                id: EntityId::Index(i),
+                change: EntityChange::Removed {
+                    classname: classname(&self.entities[i]),
+                },
+            });
+        }
+        for &j in rest_after.iter().skip(rest_before.len()) {
+            entities.push(EntityDiff {
+                id: EntityId::Index(j),
+                change: EntityChange::Added {
+                    classname: classname(&other.entities[j]),
+                },
+            });
+        }
+
+        MapDiff { counts, entities }
+    }
+}
+
+#[test]
+fn test_map_diff() {
+    use crate::lumps::entities::parse_entities;
+    use crate::lumps::lump_types::Vertex;
+
+    let vertex = || Vertex {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+    let before = ParsedLumps {
+        entities: parse_entities(concat!(
+            "{\n\"classname\" \"worldspawn\"\n}\n",
+            "{\n\"classname\" \"func_door\"\n\"targetname\" \"door\"\n\"speed\" \"100\"\n}\n",
+        )),
+        vertex_list: vec![vertex(), vertex()],
+        ..Default::default()
+    };
+    let after = ParsedLumps {
+        entities: parse_entities(concat!(
+            "{\n\"classname\" \"worldspawn\"\n}\n",
+            "{\n\"classname\" \"light\"\n}\n",
+            "{\n\"classname\" \"func_door\"\n\"targetname\" \"door\"\n\"speed\" \"200\"\n}\n",
+        )),
+        vertex_list: vec![vertex(), vertex(), vertex()],
+        ..Default::default()
+    };
+
+    assert!(before.diff(&before.clone()).is_empty());
+
+    let diff = before.diff(&after);
+    assert_eq!(
+        diff.counts,
+        [
+            CountDiff {
+                lump: LumpType::Entities,
+                before: 2,
+                after: 3
+            },
+            CountDiff {
+                lump: LumpType::Vertexes,
+                before: 2,
+                after: 3
+            },
+        ]
+    );
+    assert_eq!(
+        diff.entities,
+        [
+            EntityDiff {
+                id: EntityId::Targetname("door".to_string()),
+                change: EntityChange::Changed(vec![KeyChange {
+                    key: "speed".to_string(),
+                    before: Some("100".to_string()),
+                    after: Some("200".to_string()),
+                }]),
+            },
+            EntityDiff {
+                id: EntityId::Index(1),
+                change: EntityChange::Added {
+                    classname: Some("light".to_string())
+                },
+            },
+        ]
+    );
+}
@@ -0,0 +1,326 @@
+use crate::lumps::lump_types::*;
+use crate::lumps::math::*;
+use crate::lumps::LumpParser::ParsedLumps;
+
+use std::cmp::Ordering;
+
+/// A displacement surface subdivided into a grid and offset by its DispVerts
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisplacementMesh {
+    /// Vertices along each side of the grid, 2^power + 1
+    pub size: usize,
+    /// Vertex positions, stored row by row
+    pub positions: Vec<Vector>,
+    /// Vertex normals of the displaced surface
+    pub normals: Vec<Vector>,
+    /// Texture coordinates projected from the base face's texinfo
+    pub uvs: Vec<(f32, f32)>,
+    /// How much of a blend material's second texture shows at each vertex, from 0 to 1.
+    /// The DispVerts lump stores it from 0 to 255.
+    pub alphas: Vec<f32>,
+}
+
+impl DisplacementMesh {
+    /// Triangulates the grid into indices of positions, two triangles per quad.
+    /// The diagonal alternates in a checkerboard like the engine does, and triangles wind
+    /// counter-clockwise when seen from the side the normals face.
+    pub fn triangles(&self) -> impl Iterator<Item = [u32; 3]> + '_ {
+        let size = self.size;
+
+        // The normals were flipped to match the base face if the grid runs the other way
+        let flip = size > 1 && {
+            let across = sub(self.positions[1], self.positions[0]);
+            let down = sub(self.positions[size], self.positions[0]);
+            dot(cross(across, down), self.normals[0]) < 0.0
+        };
+
+        (0..size.saturating_sub(1)).flat_map(move |row| {
+            (0..size - 1).flat_map(move |col| {
+                let at = |r: usize, c: usize| (r * size + c) as u32;
+                let (a, b, c, d) = (
+                    at(row, col),
+                    at(row, col + 1),
+                    at(row + 1, col),
+                    at(row + 1, col + 1),
+                );
+
+                let tris = if (row * size + col) % 2 == 1 {
+                    [[a, b, d], [a, d, c]]
+                } else {
+                    [[a, b, c], [b, d, c]]
+                };
+
+                tris.map(move |[x, y, z]| if flip { [x, z, y] } else { [x, y, z] })
+            })
+        })
+    }
+}
+
+/// The engine only supports powers 2 through 4, anything far outside that is corrupt
+const MAX_DISP_POWER: i32 = 4;
+
+impl ParsedLumps {
+    /// Number of DispVerts a displacement of the given power uses, starting at its
+    /// disp_vert_start: a grid of (2^power + 1)^2 vertices.
+    /// 0 for powers outside of 0 to 4, which no valid displacement has.
+    pub fn displacement_vertex_count(power: i32) -> usize {
+        if !(0..=MAX_DISP_POWER).contains(&power) {
+            return 0;
+        }
+
+        let size = (1 << power) + 1;
+        size * size
+    }
+
+    /// Builds the vertex grid of a displacement from its base face and DispVerts.
+    /// Returns None if the base face isn't a quad or the map doesn't have the vertices for it.
+    pub fn build_displacement(&self, disp: &DisplacementInfo) -> Option<DisplacementMesh> {
+        let count = Self::displacement_vertex_count(disp.power);
+        if count == 0 || disp.disp_vert_start < 0 {
+            return None;
+        }
+
+        let face = self.displacement_base_face(disp)?;
+        let corners: Vec<Vector> = self
+            .face_vertices(face)
+            .iter()
+            .map(|v| (v.x, v.y, v.z))
+            .collect();
+
+        if corners.len() != 4 {
+            return None;
+        }
+
+        // The corner closest to start_position is the grid's (0, 0)
+        let distance = |i: usize| length(sub(corners[i], disp.start_position));
+        let start = (0..4)
+            .min_by(|&a, &b| {
+                distance(a)
+                    .partial_cmp(&distance(b))
+                    .unwrap_or(Ordering::Equal)
+            })
+            .unwrap();
+        let corners: Vec<Vector> = (0..4).map(|i| corners[(start + i) % 4]).collect();
+
+        let size = (1 << disp.power) + 1;
+        let first_vert = disp.disp_vert_start as usize;
+        let verts = self.disp_verts.get(first_vert..first_vert + count)?;
+
+        let mut mesh = DisplacementMesh {
+            size,
+            positions: Vec::with_capacity(size * size),
+            normals: Vec::with_capacity(size * size),
+            uvs: Vec::with_capacity(size * size),
+            alphas: Vec::with_capacity(size * size),
+        };
+
+        for row in 0..size {
+            let t = row as f32 / (size - 1) as f32;
+            let row_start = lerp(corners[0], corners[1], t);
+            let row_end = lerp(corners[3], corners[2], t);
+
+            for col in 0..size {
+                let base = lerp(row_start, row_end, col as f32 / (size - 1) as f32);
+                let vert = &verts[row * size + col];
+
+                mesh.positions.push(add(base, scale(vert.vec, vert.dist)));
+                // Textures are projected onto the flat base surface, not the displaced one
+                mesh.uvs.push(self.texture_uv(face, base));
+                mesh.alphas.push((vert.alpha / 255.0).clamp(0.0, 1.0));
+            }
+        }
+
+        let face_normal = self.face_normal(face);
+
+        for row in 0..size {
+            for col in 0..size {
+                let at = |r: usize, c: usize| mesh.positions[r * size + c];
+
+                // Central differences, falling back to one side at the edges of the grid
+                let across = sub(
+                    at(row, (col + 1).min(size - 1)),
+                    at(row, col.saturating_sub(1)),
+                );
+                let down = sub(
+                    at((row + 1).min(size - 1), col),
+                    at(row.saturating_sub(1), col),
+                );
+                let mut normal = normalize(cross(across, down));
+
+                if let Some(face_normal) = face_normal {
+                    if dot(normal, face_normal) < 0.0 {
+                        normal = scale(normal, -1.0);
+                    }
+                }
+
+                mesh.normals.push(normal);
+            }
+        }
+
+        Some(mesh)
+    }
+
+    /// Returns the lightmap alpha of each luxel of a displacement
+    pub fn disp_lightmap_alphas(&self, disp: &DisplacementInfo) -> &[u8] {
+        let size = match self.displacement_base_face(disp) {
+            Some(face) => face.lightmap_texture_size_in_luxels,
+            None => return &[],
+        };
+
+        if disp.lightmap_alpha_start < 0 || size[0] < 0 || size[1] < 0 {
+            return &[];
+        }
+
+        let start = disp.lightmap_alpha_start as usize;
+        let luxels = (size[0] as usize + 1) * (size[1] as usize + 1);
+        self.disp_lightmap_alphas
+            .get(start..start + luxels)
+            .unwrap_or(&[])
+    }
+
+    /// Returns the encoded lightmap sample positions of a displacement.
+    /// The encoding is variable length, so this runs up to where the next displacement's positions start.
+    pub fn disp_lightmap_sample_positions(&self, disp: &DisplacementInfo) -> &[u8] {
+        if disp.lightmap_sample_position_start < 0 {
+            return &[];
+        }
+
+        let start = disp.lightmap_sample_position_start as usize;
+        let end = self
+            .displacement_info
+            .iter()
+            .map(|other| other.lightmap_sample_position_start)
+            .filter(|&other| other > disp.lightmap_sample_position_start)
+            .min()
+            .map_or(self.disp_lightmap_sample_positions.len(), |end| {
+                end as usize
+            });
+
+        self.disp_lightmap_sample_positions
+            .get(start..end)
+            .unwrap_or(&[])
+    }
+
+    /// Projects a point with a face's texture vectors, normalized by the texture size
+    fn texture_uv(&self, face: &Face, point: Vector) -> (f32, f32) {
+        let texinfo = match self.texinfo.get(face.texinfo as usize) {
+            Some(texinfo) => texinfo,
+            None => return (0.0, 0.0),
+        };
+
+        let (width, height) = match self.texdata.get(texinfo.tex_data as usize) {
+            Some(texdata) if texdata.width > 0 && texdata.height > 0 => {
+                (texdata.width as f32, texdata.height as f32)
+            }
+            _ => (1.0, 1.0),
+        };
+
+        (
+            texinfo.s_axis().project(point) / width,
+            texinfo.t_axis().project(point) / height,
+        )
+    }
+}
+
+#[test]
+fn test_displacement_triangles() {
+    let size = 5;
+    let mut mesh = DisplacementMesh {
+        size,
+        positions: vec![],
+        normals: vec![],
+        uvs: vec![],
+        alphas: vec![],
+    };
+    for row in 0..size {
+        for col in 0..size {
+            mesh.positions.push((col as f32, row as f32, 0.0));
+            mesh.normals.push((0.0, 0.0, 1.0));
+        }
+    }
+
+    let triangles: Vec<[u32; 3]> = mesh.triangles().collect();
+    assert_eq!(triangles.len(), 2 * 4usize.pow(2));
+
+    // Every triangle faces along the normals
+    for [a, b, c] in triangles {
+        let p = |i: u32| mesh.positions[i as usize];
+        assert!(cross(sub(p(b), p(a)), sub(p(c), p(a))).2 > 0.0);
+    }
+}
+
+#[test]
+fn test_displacement_vertex_count() {
+    assert_eq!(ParsedLumps::displacement_vertex_count(2), 25);
+    assert_eq!(ParsedLumps::displacement_vertex_count(3), 81);
+    assert_eq!(ParsedLumps::displacement_vertex_count(4), 289);
+    assert_eq!(ParsedLumps::displacement_vertex_count(-1), 0);
+    assert_eq!(ParsedLumps::displacement_vertex_count(31), 0);
+}
+
+#[test]
+fn test_build_displacement() {
+    use crate::lumps::{BspParseable, LumpReader};
+
+    let vertex = |x, y| Vertex { x, y, z: 0.0 };
+    let edge = |a, b| Edge {
+        vertex_indicies: [a, b],
+    };
+    let mut disp = DisplacementInfo::from_reader(&mut LumpReader::new(&[0; 176]));
+    // Starts from the second corner of the base face
+    disp.start_position = (4.0, 0.0, 0.0);
+    disp.power = 1;
+
+    let lumps = ParsedLumps {
+        vertex_list: vec![
+            vertex(0.0, 0.0),
+            vertex(4.0, 0.0),
+            vertex(4.0, 4.0),
+            vertex(0.0, 4.0),
+        ],
+        edges: vec![edge(0, 1), edge(1, 2), edge(2, 3), edge(3, 0)],
+        surfedges: vec![0, 1, 2, 3],
+        planes: vec![Plane {
+            normal: (0.0, 0.0, 1.0),
+            dist_from_origin: 0.0,
+            r#type: 2,
+        }],
+        faces: vec![Face {
+            displacement_info: 0,
+            ..crate::fixture::face(0, 4)
+        }],
+        // Each vertex is raised by its index
+        disp_verts: (0..9)
+            .map(|i| DispVert {
+                vec: (0.0, 0.0, 1.0),
+                dist: i as f32,
+                alpha: 255.0,
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    let mesh = lumps.build_displacement(&disp).unwrap();
+
+    assert_eq!(mesh.size, 3);
+    assert_eq!(
+        mesh.positions,
+        [
+            (4.0, 0.0, 0.0),
+            (2.0, 0.0, 1.0),
+            (0.0, 0.0, 2.0),
+            (4.0, 2.0, 3.0),
+            (2.0, 2.0, 4.0),
+            (0.0, 2.0, 5.0),
+            (4.0, 4.0, 6.0),
+            (2.0, 4.0, 7.0),
+            (0.0, 4.0, 8.0),
+        ]
+    );
+    assert!(mesh.alphas.iter().all(|&alpha| alpha == 1.0));
+    assert_eq!(mesh.triangles().count(), 8);
+
+    disp.disp_vert_start = 1;
+    assert_eq!(lumps.build_displacement(&disp), None);
+}
@@ -0,0 +1,575 @@
+use crate::lumps::lump_types::{Entity, Vector};
+use crate::lumps::LumpParser::ParsedLumps;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// An entity's key/value pairs in the order they're written, keeping repeated keys.
+/// Outputs are commonly repeated, e.g. several "OnTrigger" connections.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RawEntity {
+    pub pairs: Vec<(String, String)>,
+}
+
+impl RawEntity {
+    /// Returns the first value of a key
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns every value of a key, in order
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.pairs
+            .iter()
+            .filter(move |(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Collects the pairs into a map, later values of a repeated key replacing earlier ones
+    pub fn as_map(&self) -> Entity {
+        self.pairs.iter().cloned().collect()
+    }
+
+    /// Writes the entity back out as a `{ "key" "value" ... }` block, the way the
+    /// compiler stores it in the entities lump
+    pub fn to_block(&self) -> String {
+        let mut out = "{\n".to_string();
+        for (key, value) in &self.pairs {
+            out.push_str(&format!("{} {}\n", quote(key), quote(value)));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Parses every output connection of the entity, in order
+    pub fn connections(&self) -> Vec<Connection> {
+        self.pairs
+            .iter()
+            .filter_map(|(key, value)| Connection::parse(key, value))
+            .collect()
+    }
+}
+
+/// An entity output wired to an input of other entities
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Connection {
+    /// The output firing, e.g. "OnTrigger"
+    pub output: String,
+    /// Targetname or classname of the entities receiving it
+    pub target: String,
+    pub input: String,
+    /// Overrides the value passed to the input, empty to pass the output's value
+    pub parameter: String,
+    /// Seconds to wait before firing
+    pub delay: f32,
+    /// How many times the connection fires, -1 for no limit
+    pub times_to_fire: i32,
+}
+
+impl Connection {
+    /// Parses a "target,input,parameter,delay,times" value.
+    /// Newer maps separate the fields with the ESC character instead of commas.
+    /// Returns None for values that aren't connections.
+    pub fn parse(output: &str, value: &str) -> Option<Connection> {
+        let delimiter = if value.contains('\x1b') { '\x1b' } else { ',' };
+        let fields: Vec<&str> = value.split(delimiter).collect();
+
+        if fields.len() != 5 {
+            return None;
+        }
+
+        Some(Connection {
+            output: output.to_string(),
+            target: fields[0].to_string(),
+            input: fields[1].to_string(),
+            parameter: fields[2].to_string(),
+            delay: fields[3].trim().parse().ok()?,
+            times_to_fire: fields[4].trim().parse().ok()?,
+        })
+    }
+}
+
+/// Decodes the bytes of the entities lump into text.
+///
+/// The lump is null terminated, so anything from the first null byte on is dropped.
+/// The text is read as UTF-8, but maps compiled on Windows can store values such as
+/// `message` in the system code page, so a lump that isn't valid UTF-8 is read as
+/// Latin-1 instead. That keeps every byte as a character rather than replacing them.
+pub fn decode_entities_lump(data: &[u8]) -> Cow<'_, str> {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    let data = &data[..end];
+
+    match std::str::from_utf8(data) {
+        Ok(text) => Cow::Borrowed(text),
+        Err(_) => Cow::Owned(data.iter().map(|&b| b as char).collect()),
+    }
+}
+
+/// Parses the text of the entities lump into a list of entities.
+///
+/// The lump is a series of `{ "key" "value" ... }` blocks. Quoted strings are
+/// read up to the next unescaped `"`, so values may contain `\"` as well as
+/// `{` and `}` without ending the string or the entity block.
+pub fn parse_entities(text: &str) -> Vec<Entity> {
+    parse_raw_entities(text)
+        .iter()
+        .map(RawEntity::as_map)
+        .collect()
+}
+
+/// Parses the entities lump like parse_entities, keeping the pairs in order along with repeated keys
+pub fn parse_raw_entities(text: &str) -> Vec<RawEntity> {
+    let mut entities = vec![];
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '{' {
+            continue; // Anything outside of a block is ignored
+        }
+
+        let mut entity = RawEntity::default();
+        let mut key = None;
+
+        while let Some(c) = chars.next() {
+            match c {
+                '}' => break,
+                '"' => {
+                    let string = read_quoted(&mut chars);
+                    // Strings alternate between keys and their values
+                    match key.take() {
+                        None => key = Some(string),
+                        Some(key) => entity.pairs.push((key, string)),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        entities.push(entity);
+    }
+
+    entities
+}
+
+/// Typed accessors for the string values of an entity
+pub trait EntityExt {
+    /// Parses a value as three whitespace separated numbers
+    fn get_vec3(&self, key: &str) -> Option<Vector>;
+    fn get_f32(&self, key: &str) -> Option<f32>;
+    fn get_i32(&self, key: &str) -> Option<i32>;
+
+    /// Parses the entity's output connections. Only the last of a repeated output survives in
+    /// a map, use RawEntity::connections to get all of them.
+    fn connections(&self) -> Vec<Connection>;
+
+    /// The entity's position in the world
+    fn origin(&self) -> Option<Vector> {
+        self.get_vec3("origin")
+    }
+
+    /// The entity's rotation as pitch, yaw and roll in degrees
+    fn angles(&self) -> Option<Vector> {
+        self.get_vec3("angles")
+    }
+}
+
+impl EntityExt for Entity {
+    fn get_vec3(&self, key: &str) -> Option<Vector> {
+        let mut parts = self.get(key)?.split_whitespace().map(|s| s.parse().ok());
+        let vec = (parts.next()??, parts.next()??, parts.next()??);
+
+        // Anything past the third number means the value isn't a vector
+        match parts.next() {
+            None => Some(vec),
+            Some(_) => None,
+        }
+    }
+
+    fn get_f32(&self, key: &str) -> Option<f32> {
+        self.get(key)?.trim().parse().ok()
+    }
+
+    fn get_i32(&self, key: &str) -> Option<i32> {
+        self.get(key)?.trim().parse().ok()
+    }
+
+    fn connections(&self) -> Vec<Connection> {
+        self.iter()
+            .filter_map(|(key, value)| Connection::parse(key, value))
+            .collect()
+    }
+}
+
+/// Map wide settings stored on the worldspawn entity
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WorldSpawnInfo {
+    /// Name of the 2D skybox texture
+    pub sky_name: Option<String>,
+    /// Shown to players when they join
+    pub message: Option<String>,
+    /// Material holding the detail prop sprites, e.g. "detail/detailsprites"
+    pub detail_material: Option<String>,
+    /// The .vbsp file defining which detail props are placed on which materials
+    pub detail_vbsp: Option<String>,
+}
+
+/// Fog drawn in the 3D skybox, from the fog keys of the sky_camera entity
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FogParams {
+    pub enabled: bool,
+    pub color: [u8; 3],
+    /// Second color, blended with the first by view direction when blend is on
+    pub color2: [u8; 3],
+    pub blend: bool,
+    /// The view direction color is used when looking along
+    pub direction: Vector,
+    /// Distance from the camera the fog starts at
+    pub start: f32,
+    /// Distance from the camera the fog reaches full density at
+    pub end: f32,
+    /// 0 to 1
+    pub max_density: f32,
+}
+
+/// The 3D skybox camera. The skybox is drawn from `origin`, with the rest of the
+/// world scaled down around it by `scale`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SkyCamera {
+    pub origin: Vector,
+    pub scale: i32,
+    pub fog: FogParams,
+}
+
+/// Parses an "r g b" color key
+fn get_color(entity: &Entity, key: &str) -> Option<[u8; 3]> {
+    let (r, g, b) = entity.get_vec3(key)?;
+    Some([r, g, b].map(|c| c.clamp(0.0, 255.0) as u8))
+}
+
+impl ParsedLumps {
+    /// Reads the sky_camera entity, None if the map has no 3D skybox.
+    /// Missing keys take the defaults Hammer gives a new sky_camera.
+    pub fn sky_camera(&self) -> Option<SkyCamera> {
+        let camera = self
+            .entities
+            .iter()
+            .find(|entity| entity.get("classname").map(String::as_str) == Some("sky_camera"))?;
+        let flag = |key: &str| camera.get_i32(key).is_some_and(|value| value != 0);
+
+        Some(SkyCamera {
+            origin: camera.origin().unwrap_or((0.0, 0.0, 0.0)),
+            scale: camera.get_i32("scale").unwrap_or(16),
+            fog: FogParams {
+                enabled: flag("fogenable"),
+                color: get_color(camera, "fogcolor").unwrap_or([255, 255, 255]),
+                color2: get_color(camera, "fogcolor2").unwrap_or([255, 255, 255]),
+                blend: flag("fogblend"),
+                direction: camera.get_vec3("fogdir").unwrap_or((1.0, 0.0, 0.0)),
+                start: camera.get_f32("fogstart").unwrap_or(500.0),
+                end: camera.get_f32("fogend").unwrap_or(2000.0),
+                max_density: camera.get_f32("fogmaxdensity").unwrap_or(1.0),
+            },
+        })
+    }
+
+    /// Writes the entities back out as the text of the entities lump, which parses to the
+    /// same entities. `entities` is what gets written, so edits made to it are kept;
+    /// raw_entities only decides the layout. An entity that still matches its raw entity is
+    /// written with the raw keys in order, repeats included. An edited entity keeps the raw
+    /// key order for the keys it still has, with one value per key, and then any new keys
+    /// in sorted order. Entities without a raw entity to match, as when they were built by
+    /// hand, are written with their keys sorted.
+    ///
+    /// Values are written byte for byte apart from escaping quotes. A value ending in a
+    /// backslash can't be read back, as the backslash escapes its closing quote.
+    pub fn entities_to_string(&self) -> String {
+        let raw_entities = if self.raw_entities.len() == self.entities.len() {
+            &self.raw_entities[..]
+        } else {
+            &[]
+        };
+
+        self.entities
+            .iter()
+            .enumerate()
+            .map(|(i, entity)| match raw_entities.get(i) {
+                Some(raw) if raw.as_map() == *entity => raw.to_block(),
+                raw => {
+                    let mut pairs: Vec<(String, String)> = vec![];
+                    for (key, _) in raw.iter().flat_map(|raw| &raw.pairs) {
+                        if let Some(value) = entity.get(key) {
+                            if !pairs.iter().any(|(k, _)| k == key) {
+                                pairs.push((key.clone(), value.clone()));
+                            }
+                        }
+                    }
+
+                    let mut new: Vec<(String, String)> = entity
+                        .iter()
+                        .filter(|(key, _)| !pairs.iter().any(|(k, _)| k == *key))
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect();
+                    new.sort();
+                    pairs.extend(new);
+
+                    RawEntity { pairs }.to_block()
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the worldspawn entity, which is always the first entity in a valid map
+    pub fn worldspawn(&self) -> Option<&Entity> {
+        self.entities
+            .iter()
+            .find(|entity| entity.get("classname").map(String::as_str) == Some("worldspawn"))
+    }
+
+    /// Reads the typed settings from the worldspawn entity
+    pub fn worldspawn_info(&self) -> Option<WorldSpawnInfo> {
+        let worldspawn = self.worldspawn()?;
+        let get = |key: &str| worldspawn.get(key).cloned();
+
+        Some(WorldSpawnInfo {
+            sky_name: get("skyname"),
+            message: get("message"),
+            detail_material: get("detailmaterial"),
+            detail_vbsp: get("detailvbsp"),
+        })
+    }
+
+    /// Maps each lowercased targetname to the indices of the entities bearing it.
+    /// Names aren't unique, and the engine compares them case insensitively.
+    pub fn targetname_index(&self) -> HashMap<String, Vec<usize>> {
+        let mut index: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, entity) in self.entities.iter().enumerate() {
+            if let Some(name) = entity.get("targetname") {
+                index.entry(name.to_ascii_lowercase()).or_default().push(i);
+            }
+        }
+        index
+    }
+
+    /// Finds the entities an I/O connection targeting `name` would reach.
+    /// Special names resolved at runtime such as `!activator`, `!caller` and `!player`
+    /// don't belong to any entity in the map, so they return nothing.
+    pub fn entities_by_targetname<'a>(&'a self, name: &str) -> Vec<&'a Entity> {
+        self.entities
+            .iter()
+            .filter(|entity| {
+                entity
+                    .get("targetname")
+                    .is_some_and(|targetname| targetname.eq_ignore_ascii_case(name))
+            })
+            .collect()
+    }
+}
+
+/// Quotes a string so read_quoted reads it back the same. Only quotes are escaped, the
+/// engine reads everything else byte for byte, backslashes included.
+fn quote(string: &str) -> String {
+    format!("\"{}\"", string.replace('"', "\\\""))
+}
+
+/// Reads the rest of a quoted string, with the opening quote already consumed.
+/// `\"` is unescaped to a quote, any other backslash is kept as-is since paths use them.
+fn read_quoted(chars: &mut Peekable<Chars>) -> String {
+    let mut out = String::new();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' if chars.peek() == Some(&'"') => {
+                out.push('"');
+                chars.next();
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[test]
+fn test_entities_escaped_quote() {
+    let entities =
+        parse_entities("{\n\"classname\" \"game_text\"\n\"message\" \"He said \\\"hi\\\"\"\n}\n");
+
+    assert_eq!(entities.len(), 1);
+    assert_eq!(entities[0]["classname"], "game_text");
+    assert_eq!(entities[0]["message"], "He said \"hi\"");
+}
+
+#[test]
+fn test_entities_brace_in_value() {
+    let entities = parse_entities(
+        "{\n\"classname\" \"worldspawn\"\n\"message\" \"a } b { c\"\n}\n{\n\"classname\" \"info_null\"\n}\n",
+    );
+
+    assert_eq!(entities.len(), 2);
+    assert_eq!(entities[0]["message"], "a } b { c");
+    assert_eq!(entities[1]["classname"], "info_null");
+}
+
+#[test]
+fn test_entity_typed_values() {
+    let entities =
+        parse_entities("{\n\"origin\" \"1 -2.5 3\"\n\"angles\" \"0 90\"\n\"health\" \"100\"\n}\n");
+
+    assert_eq!(entities[0].origin(), Some((1.0, -2.5, 3.0)));
+    assert_eq!(entities[0].angles(), None);
+    assert_eq!(entities[0].get_i32("health"), Some(100));
+    assert_eq!(entities[0].get_f32("health"), Some(100.0));
+    assert_eq!(entities[0].get_f32("missing"), None);
+}
+
+#[test]
+fn test_entity_connections() {
+    let entities = parse_raw_entities(
+        "{\n\"classname\" \"trigger_once\"\n\"OnTrigger\" \"door,Open,,0,-1\"\n\"OnTrigger\" \"relay\x1bTrigger\x1ba,b\x1b1.5\x1b1\"\n\"wait\" \"1,2\"\n}\n",
+    );
+
+    let connections = entities[0].connections();
+    assert_eq!(connections.len(), 2);
+    assert_eq!(connections[0].target, "door");
+    assert_eq!(connections[0].input, "Open");
+    assert_eq!(connections[0].parameter, "");
+    assert_eq!(connections[0].times_to_fire, -1);
+    assert_eq!(connections[1].parameter, "a,b");
+    assert_eq!(connections[1].delay, 1.5);
+
+    assert_eq!(entities[0].get_all("OnTrigger").count(), 2);
+    assert_eq!(entities[0].as_map().connections().len(), 1);
+}
+
+#[test]
+fn test_entities_by_targetname() {
+    let lumps = ParsedLumps {
+        entities: parse_entities(concat!(
+            "{\n\"classname\" \"worldspawn\"\n}\n",
+            "{\n\"classname\" \"logic_relay\"\n\"targetname\" \"Relay\"\n}\n",
+            "{\n\"classname\" \"func_door\"\n\"targetname\" \"door\"\n}\n",
+            "{\n\"classname\" \"logic_relay\"\n\"targetname\" \"relay\"\n}\n",
+        )),
+        ..Default::default()
+    };
+
+    let relays = lumps.entities_by_targetname("relay");
+    assert_eq!(relays.len(), 2);
+    assert!(lumps.entities_by_targetname("!activator").is_empty());
+
+    let index = lumps.targetname_index();
+    assert_eq!(index["relay"], vec![1, 3]);
+    assert_eq!(index["door"], vec![2]);
+    assert_eq!(index.len(), 2);
+}
+
+#[test]
+fn test_entities_lump_encoding() {
+    let lump = "{\n\"classname\" \"worldspawn\"\n\"message\" \"Caf\u{e9}\"\n}\n\0";
+    let text = decode_entities_lump(lump.as_bytes());
+    assert!(!text.contains('\0'));
+    let entities = parse_entities(&text);
+    assert_eq!(entities[0]["message"], "Caf\u{e9}");
+
+    // The same value written in Latin-1, with padding after the terminator
+    let latin1 = b"{\n\"message\" \"Caf\xe9\"\n}\n\0\0\"junk\"";
+    let entities = parse_entities(&decode_entities_lump(latin1));
+    assert_eq!(entities.len(), 1);
+    assert_eq!(entities[0]["message"], "Caf\u{e9}");
+}
+
+#[test]
+fn test_sky_camera() {
+    let mut lumps = ParsedLumps {
+        entities: parse_entities("{\n\"classname\" \"worldspawn\"\n}\n"),
+        ..Default::default()
+    };
+    assert!(lumps.sky_camera().is_none());
+
+    lumps.entities = parse_entities(concat!(
+        "{\n\"classname\" \"sky_camera\"\n\"origin\" \"128 -64 32\"\n\"scale\" \"32\"\n",
+        "\"fogenable\" \"1\"\n\"fogcolor\" \"40 50 300\"\n\"fogend\" \"4000\"\n}\n",
+    ));
+    let camera = lumps.sky_camera().unwrap();
+
+    assert_eq!(camera.origin, (128.0, -64.0, 32.0));
+    assert_eq!(camera.scale, 32);
+    assert!(camera.fog.enabled);
+    assert_eq!(camera.fog.color, [40, 50, 255]);
+    assert_eq!(camera.fog.start, 500.0);
+    assert_eq!(camera.fog.end, 4000.0);
+}
+
+#[test]
+fn test_entities_to_string() {
+    let text = concat!(
+        "{\n\"classname\" \"worldspawn\"\n}\n",
+        "{\n\"classname\" \"logic_relay\"\n\"OnTrigger\" \"a,Open,,0,-1\"\n",
+        "\"OnTrigger\" \"b,Close,,1,-1\"\n\"message\" \"say \\\"hi\\\" {}\"\n}\n",
+    );
+    let lumps = ParsedLumps {
+        entities: parse_entities(text),
+        raw_entities: parse_raw_entities(text),
+        ..Default::default()
+    };
+
+    let written = lumps.entities_to_string();
+    assert_eq!(written, text);
+    assert_eq!(parse_raw_entities(&written), lumps.raw_entities);
+
+    // Backslashes are written as they are, like the engine reads them
+    let paths_text = concat!(
+        "{\n\"classname\" \"info_null\"\n\"material\" \"materials\\foo\"\n",
+        "\"share\" \"\\\\server\\maps\"\n}\n",
+    );
+    let paths = parse_raw_entities(paths_text);
+    assert_eq!(paths[0].get("material"), Some("materials\\foo"));
+    assert_eq!(paths[0].get("share"), Some("\\\\server\\maps"));
+    let path_lumps = ParsedLumps {
+        entities: paths.iter().map(RawEntity::as_map).collect(),
+        raw_entities: paths.clone(),
+        ..Default::default()
+    };
+    assert_eq!(path_lumps.entities_to_string(), paths_text);
+
+    // Edits to the entities are written, keeping the raw key order
+    let mut edited = lumps.clone();
+    edited.entities[1].insert("message".to_string(), "bye".to_string());
+    edited.entities[1].insert("delay".to_string(), "2".to_string());
+    let written = parse_raw_entities(&edited.entities_to_string());
+    assert_eq!(written[0], lumps.raw_entities[0]);
+    assert_eq!(
+        written[1].pairs,
+        [
+            ("classname".to_string(), "logic_relay".to_string()),
+            ("OnTrigger".to_string(), "b,Close,,1,-1".to_string()),
+            ("message".to_string(), "bye".to_string()),
+            ("delay".to_string(), "2".to_string()),
+        ]
+    );
+    assert_eq!(
+        parse_entities(&edited.entities_to_string()),
+        edited.entities
+    );
+
+    // Without the raw entities the keys are sorted and the last repeat wins
+    let maps_only = ParsedLumps {
+        entities: lumps.entities.clone(),
+        ..Default::default()
+    };
+    assert_eq!(
+        parse_entities(&maps_only.entities_to_string()),
+        lumps.entities
+    );
+}
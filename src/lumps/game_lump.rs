@@ -0,0 +1,776 @@
+use crate::error::BspError;
+use crate::lumps::lump_types::*;
+use crate::lumps::LumpParser::{decompress_block, ParsedLumps};
+use crate::lumps::{clamped_slice, LumpReader};
+
+/// Set in a game lump's flags when its data is LZMA compressed
+pub const GAME_LUMP_COMPRESSED: u16 = 0x0001;
+
+/// A game specific sub-lump of the game lump, such as static props ("sprp")
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GameLump {
+    /// Four character code in reading order, e.g. b"sprp"
+    pub id: [u8; 4],
+    pub flags: u16,
+    pub version: u16,
+    /// Offset from the start of the file, not the game lump
+    pub fileofs: i32,
+    /// Length of the data, which for a compressed sub-lump is its decompressed length
+    pub filelen: i32,
+    /// The sub-lump's bytes, decompressed if they're stored compressed
+    pub data: Vec<u8>,
+}
+
+/// A summary of a game lump for listing what a map contains
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameLumpInfo {
+    pub id: [u8; 4],
+    pub id_str: String,
+    pub version: u16,
+    pub flags: u16,
+    pub length: usize,
+    pub compressed: bool,
+}
+
+/// Where a detail sprite sits in the detail sprite sheet
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DetailSprite {
+    /// Upper left corner of the sprite's quad
+    pub upper_left: (f32, f32),
+    /// Lower right corner of the sprite's quad
+    pub lower_right: (f32, f32),
+    /// Upper left texture coordinate
+    pub tex_upper_left: (f32, f32),
+    /// Lower right texture coordinate
+    pub tex_lower_right: (f32, f32),
+}
+
+impl BspParseable for DetailSprite {
+    fn from_reader(data: &mut LumpReader) -> Self {
+        let mut pair = || (data.read_f32(), data.read_f32());
+        Self {
+            upper_left: pair(),
+            lower_right: pair(),
+            tex_upper_left: pair(),
+            tex_lower_right: pair(),
+        }
+    }
+}
+
+/// A single placed detail prop, such as a tuft of grass
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DetailObject {
+    pub origin: Vector,
+    /// Pitch, yaw and roll in degrees
+    pub angles: Vector,
+    /// Index into the model names, or the sprites for sprite types
+    pub detail_model: u16,
+    /// The leaf the prop is in
+    pub leaf: u16,
+    pub lighting: LightmapSample,
+    pub light_styles: u32,
+    pub light_style_count: u8,
+    /// How much the prop sways in the wind
+    pub sway_amount: u8,
+    pub shape_angle: u8,
+    pub shape_size: u8,
+    /// 0 for none, 1 to face the camera, 2 to rotate around z to face the camera
+    pub orientation: u8,
+    /// 0 for a model, 1 for a sprite, 2 and 3 for sprite shapes
+    pub r#type: u8,
+    pub scale: f32,
+}
+
+impl BspParseable for DetailObject {
+    fn from_reader(data: &mut LumpReader) -> Self {
+        let origin = data.read_vec3();
+        let angles = data.read_vec3();
+        let detail_model = data.read_u16();
+        let leaf = data.read_u16();
+        let lighting = LightmapSample::from_reader(data);
+        let light_styles = data.read_u32();
+        let light_style_count = data.read_u8();
+        let sway_amount = data.read_u8();
+        let shape_angle = data.read_u8();
+        let shape_size = data.read_u8();
+        let orientation = data.read_u8();
+        data.skip_bytes(3); // Padding
+        let r#type = data.read_u8();
+        data.skip_bytes(3); // Padding
+        let scale = data.read_f32();
+
+        Self {
+            origin,
+            angles,
+            detail_model,
+            leaf,
+            lighting,
+            light_styles,
+            light_style_count,
+            sway_amount,
+            shape_angle,
+            shape_size,
+            orientation,
+            r#type,
+            scale,
+        }
+    }
+}
+
+/// The contents of the "dprp" game lump
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DetailProps {
+    /// Model paths used by model detail props
+    pub models: Vec<String>,
+    pub sprites: Vec<DetailSprite>,
+    pub objects: Vec<DetailObject>,
+}
+
+/// Lighting for one of a detail prop's extra light styles, from the "dplt" and "dplh" lumps.
+/// The prop's lighting for the normal style is in DetailObject::lighting.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DetailPropLightStyle {
+    pub lighting: LightmapSample,
+    pub style: u8,
+}
+
+impl BspParseable for DetailPropLightStyle {
+    fn from_reader(data: &mut LumpReader) -> Self {
+        Self {
+            lighting: LightmapSample::from_reader(data),
+            style: data.read_u8(),
+        }
+    }
+}
+
+impl DetailObject {
+    /// Returns the prop's range of the light styles from ParsedLumps::detail_prop_lighting
+    pub fn styles<'a>(&self, lighting: &'a [DetailPropLightStyle]) -> &'a [DetailPropLightStyle] {
+        clamped_slice(
+            lighting,
+            self.light_styles as i64,
+            self.light_style_count as i64,
+        )
+    }
+}
+
+/// Reads a count followed by that many items, or None if the data is too short to hold them
+fn read_counted<T: BspParseable>(data: &mut LumpReader, size: usize) -> Option<Vec<T>> {
+    if data.remaining() < 4 {
+        return None;
+    }
+
+    let count = data.read_i32();
+    if count < 0 || data.remaining() < count as usize * size {
+        return None;
+    }
+
+    Some((0..count).map(|_| T::from_reader(data)).collect())
+}
+
+impl DetailProps {
+    /// Parses the "dprp" lump. Only version 4, used by every shipped Source game, is supported.
+    pub fn from_reader_versioned(data: &mut LumpReader, version: u16) -> Option<Self> {
+        if version != 4 {
+            return None;
+        }
+
+        let models = read_counted::<ModelName>(data, 128)?
+            .into_iter()
+            .map(|name| name.0)
+            .collect();
+        let sprites = read_counted(data, 32)?;
+        let objects = read_counted(data, 52)?;
+
+        Some(Self {
+            models,
+            sprites,
+            objects,
+        })
+    }
+}
+
+/// A NUL padded 128 byte model path
+struct ModelName(String);
+
+impl BspParseable for ModelName {
+    fn from_reader(data: &mut LumpReader) -> Self {
+        let bytes = data.with_limit(128);
+        let bytes = bytes.get_data();
+        let end = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+
+        ModelName(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+}
+
+/// A model placed in the map by the compiler, from the "sprp" game lump
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StaticProp {
+    pub origin: Vector,
+    /// Pitch, yaw and roll in degrees
+    pub angles: Vector,
+    /// Index into the model names
+    pub prop_type: u16,
+    /// The prop's range of StaticProps::leaves
+    pub first_leaf: u16,
+    pub leaf_count: u16,
+    /// 0 for not solid, 2 for its bounding box and 6 for its collision model
+    pub solid: u8,
+    /// Widened to 32 bits by the Source 2013 layout
+    pub flags: u32,
+    pub skin: i32,
+    pub fade_min_dist: f32,
+    pub fade_max_dist: f32,
+    /// Where the prop is lit from
+    pub lighting_origin: Vector,
+    /// Since version 5, 1 before
+    pub forced_fade_scale: f32,
+    /// Versions 6 and 7 and the Source 2013 layout, 0 otherwise
+    pub min_dx_level: u16,
+    pub max_dx_level: u16,
+    /// Since version 8, 0 before
+    pub min_cpu_level: u8,
+    pub max_cpu_level: u8,
+    pub min_gpu_level: u8,
+    pub max_gpu_level: u8,
+    /// RGBA tint, since version 7. Opaque white before.
+    pub diffuse_modulation: [u8; 4],
+    /// Since version 9
+    pub disable_x360: bool,
+    /// Since version 10
+    pub flags_ex: u32,
+    /// Lightmap size of props lit per texel, only in the Source 2013 layout
+    pub lightmap_resolution: (u16, u16),
+    /// Since version 11, 1 before
+    pub uniform_scale: f32,
+}
+
+/// Size of a version 10 prop written by Source 2013 games, which use a layout of their own
+const SOURCE_2013_PROP_SIZE: usize = 72;
+
+impl StaticProp {
+    /// Size in bytes of a prop in the given "sprp" version, None for versions that
+    /// aren't supported. Source 2013 games write version 10 props in 72 bytes instead.
+    pub fn size(version: u16) -> Option<usize> {
+        Some(match version {
+            4 => 56,
+            5 => 60,
+            6 => 64,
+            7 | 8 => 68,
+            9 => 72,
+            10 => 76,
+            11 => 80,
+            _ => return None,
+        })
+    }
+
+    fn from_reader_versioned(data: &mut LumpReader, version: u16, source_2013: bool) -> Self {
+        let mut prop = Self {
+            origin: data.read_vec3(),
+            angles: data.read_vec3(),
+            prop_type: data.read_u16(),
+            first_leaf: data.read_u16(),
+            leaf_count: data.read_u16(),
+            solid: data.read_u8(),
+            flags: data.read_u8() as u32,
+            skin: data.read_i32(),
+            fade_min_dist: data.read_f32(),
+            fade_max_dist: data.read_f32(),
+            lighting_origin: data.read_vec3(),
+            forced_fade_scale: 1.0,
+            min_dx_level: 0,
+            max_dx_level: 0,
+            min_cpu_level: 0,
+            max_cpu_level: 0,
+            min_gpu_level: 0,
+            max_gpu_level: 0,
+            diffuse_modulation: [255; 4],
+            disable_x360: false,
+            flags_ex: 0,
+            lightmap_resolution: (0, 0),
+            uniform_scale: 1.0,
+        };
+
+        if version >= 5 {
+            prop.forced_fade_scale = data.read_f32();
+        }
+
+        if source_2013 {
+            prop.min_dx_level = data.read_u16();
+            prop.max_dx_level = data.read_u16();
+            prop.flags = data.read_u32();
+            prop.lightmap_resolution = (data.read_u16(), data.read_u16());
+            return prop;
+        }
+
+        if version == 6 || version == 7 {
+            prop.min_dx_level = data.read_u16();
+            prop.max_dx_level = data.read_u16();
+        }
+        if version >= 8 {
+            prop.min_cpu_level = data.read_u8();
+            prop.max_cpu_level = data.read_u8();
+            prop.min_gpu_level = data.read_u8();
+            prop.max_gpu_level = data.read_u8();
+        }
+        if version >= 7 {
+            prop.diffuse_modulation = [
+                data.read_u8(),
+                data.read_u8(),
+                data.read_u8(),
+                data.read_u8(),
+            ];
+        }
+        if version >= 9 {
+            prop.disable_x360 = data.read_u8() != 0;
+            data.skip_bytes(3); // Padding
+        }
+        if version >= 10 {
+            prop.flags_ex = data.read_u32();
+        }
+        if version >= 11 {
+            prop.uniform_scale = data.read_f32();
+        }
+
+        prop
+    }
+
+    /// Returns the prop's range of the leaves from StaticProps::leaves
+    pub fn leaves<'a>(&self, leaves: &'a [u16]) -> &'a [u16] {
+        clamped_slice(leaves, self.first_leaf as i64, self.leaf_count as i64)
+    }
+}
+
+/// The contents of the "sprp" game lump
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StaticProps {
+    /// Model paths, indexed by StaticProp::prop_type
+    pub models: Vec<String>,
+    /// The leaves each prop is in, indexed by StaticProp::first_leaf
+    pub leaves: Vec<u16>,
+    pub props: Vec<StaticProp>,
+}
+
+impl StaticProps {
+    /// Parses the "sprp" lump, versions 4 through 11.
+    ///
+    /// The size of each prop is worked out from what's left of the lump, since Source 2013
+    /// games write version 10 with a different layout. Anything past the fields of a known
+    /// layout is skipped, and props too small for their version aren't read.
+    pub fn from_reader_versioned(data: &mut LumpReader, version: u16) -> Option<Self> {
+        let expected = StaticProp::size(version)?;
+
+        let models = read_counted::<ModelName>(data, 128)?
+            .into_iter()
+            .map(|name| name.0)
+            .collect();
+        let leaves = read_counted(data, 2)?;
+
+        if data.remaining() < 4 {
+            return None;
+        }
+        let count = data.read_i32();
+        if count < 0 {
+            return None;
+        }
+        let count = count as usize;
+
+        let stride = data.remaining().checked_div(count).unwrap_or(expected);
+        let source_2013 = version == 10 && stride == SOURCE_2013_PROP_SIZE;
+        if stride < expected && !source_2013 {
+            return None;
+        }
+
+        let props = (0..count)
+            .map(|_| {
+                StaticProp::from_reader_versioned(
+                    &mut data.with_limit(stride),
+                    version,
+                    source_2013,
+                )
+            })
+            .collect();
+
+        Some(Self {
+            models,
+            leaves,
+            props,
+        })
+    }
+
+    /// Returns the model path of a prop
+    pub fn model(&self, prop: &StaticProp) -> Option<&str> {
+        self.models.get(prop.prop_type as usize).map(String::as_str)
+    }
+}
+
+/// Reads the game lump directory and copies each sub-lump out of the file, decompressing
+/// any that are LZMA compressed. Entries pointing outside of the file are kept with no data.
+///
+/// Compressed sub-lumps are each their own LZMA block and their `filelen` holds the
+/// decompressed size, so the stored length is taken from the next entry's offset instead.
+/// The last sub-lump runs to `lump_end`, the file offset the game lump ends at. Compilers
+/// add a terminating entry with no data to mark it, which is skipped. Compressed sub-lumps
+/// larger than `max_decompressed_size` are returned as errors.
+pub fn parse_game_lumps(
+    data: &mut LumpReader,
+    full_data: &[u8],
+    lump_end: usize,
+    max_decompressed_size: usize,
+) -> Vec<Result<GameLump, BspError>> {
+    let mut entries = vec![];
+
+    if data.remaining() < 4 {
+        return vec![];
+    }
+
+    let count = data.read_i32();
+
+    for _ in 0..count {
+        // Each entry is 16 bytes, stop at a directory that claims more than it holds
+        if data.remaining() < 16 {
+            break;
+        }
+
+        // The id is stored as an int, so its characters are reversed on disk
+        let id = data.read_u32().to_be_bytes();
+        let flags = data.read_u16();
+        let version = data.read_u16();
+        let fileofs = data.read_i32();
+        let filelen = data.read_i32();
+
+        entries.push(GameLump {
+            id,
+            flags,
+            version,
+            fileofs,
+            filelen,
+            data: vec![],
+        });
+    }
+
+    let next_offsets: Vec<usize> = entries
+        .iter()
+        .skip(1)
+        .map(|lump| lump.fileofs.max(0) as usize)
+        .chain(std::iter::once(lump_end))
+        .collect();
+
+    entries
+        .into_iter()
+        .zip(next_offsets)
+        .filter(|(lump, _)| !(lump.id == [0; 4] && lump.filelen == 0))
+        .map(|(mut lump, next_offset)| {
+            if lump.fileofs < 0 || lump.filelen < 0 {
+                return Ok(lump);
+            }
+
+            let start = lump.fileofs as usize;
+            if lump.is_compressed() {
+                if let Some(bytes) = full_data.get(start..next_offset.max(start)) {
+                    lump.data = decompress_block(bytes, max_decompressed_size)?;
+                }
+            } else if let Some(bytes) = full_data.get(start..start + lump.filelen as usize) {
+                lump.data = bytes.to_vec();
+            }
+
+            Ok(lump)
+        })
+        .collect()
+}
+
+impl GameLump {
+    /// Whether the sub-lump was stored compressed. Its data has already been decompressed.
+    pub fn is_compressed(&self) -> bool {
+        self.flags & GAME_LUMP_COMPRESSED != 0
+    }
+}
+
+impl ParsedLumps {
+    /// Lists the sub-lumps of the game lump, e.g. to check for static props before decoding them
+    pub fn game_lump_entries(&self) -> Vec<GameLumpInfo> {
+        self.game_lumps
+            .iter()
+            .map(|lump| GameLumpInfo {
+                id: lump.id,
+                id_str: String::from_utf8_lossy(&lump.id).into_owned(),
+                version: lump.version,
+                flags: lump.flags,
+                length: lump.filelen.max(0) as usize,
+                compressed: lump.is_compressed(),
+            })
+            .collect()
+    }
+
+    /// A reader over game lump data in the map's byte order
+    fn reader<'a>(&self, data: &'a [u8]) -> LumpReader<'a> {
+        let mut reader = LumpReader::new(data);
+        reader.set_endianness(self.endianness);
+        reader
+    }
+
+    /// Returns the game lump with the given id, e.g. b"sprp"
+    pub fn game_lump(&self, id: &[u8; 4]) -> Option<&GameLump> {
+        self.game_lumps.iter().find(|lump| &lump.id == id)
+    }
+
+    /// Decodes the detail prop dictionaries and placements, None if the map has none
+    /// or they're in an unsupported version
+    pub fn detail_prop_data(&self) -> Option<DetailProps> {
+        let lump = self.game_lump(b"dprp")?;
+        DetailProps::from_reader_versioned(&mut self.reader(&lump.data), lump.version)
+    }
+
+    /// Decodes the light styles of the detail props from the HDR ("dplh") or LDR ("dplt")
+    /// lump. Maps compiled with only one set of lighting fall back to it whichever is asked
+    /// for. Each prop's styles are found with DetailObject::styles.
+    pub fn detail_prop_lighting(&self, hdr: bool) -> Vec<DetailPropLightStyle> {
+        let (wanted, other) = if hdr {
+            (b"dplh", b"dplt")
+        } else {
+            (b"dplt", b"dplh")
+        };
+
+        self.game_lump(wanted)
+            .or_else(|| self.game_lump(other))
+            .and_then(|lump| read_counted(&mut self.reader(&lump.data), 5))
+            .unwrap_or_default()
+    }
+
+    /// Decodes the static prop dictionaries and placements, None if the map has none
+    /// or they're in an unsupported version
+    pub fn static_prop_data(&self) -> Option<StaticProps> {
+        let lump = self.game_lump(b"sprp")?;
+        StaticProps::from_reader_versioned(&mut self.reader(&lump.data), lump.version)
+    }
+
+    /// Returns every static prop placed in the map
+    pub fn static_props(&self) -> Vec<StaticProp> {
+        self.static_prop_data()
+            .map_or_else(Vec::new, |static_props| static_props.props)
+    }
+
+    /// Returns the model paths static props use, indexed by StaticProp::prop_type
+    pub fn static_prop_models(&self) -> Vec<String> {
+        self.static_prop_data()
+            .map_or_else(Vec::new, |static_props| static_props.models)
+    }
+
+    /// Returns every detail prop placed in the map
+    pub fn detail_props(&self) -> Vec<DetailObject> {
+        self.detail_prop_data()
+            .map_or_else(Vec::new, |detail_props| detail_props.objects)
+    }
+}
+
+#[test]
+fn test_game_lump_directory() {
+    let mut file = vec![0u8; 8];
+    file.extend_from_slice(&2i32.to_le_bytes());
+    for (id, version, fileofs) in [(b"sprp", 10u16, 0i32), (b"dprp", 4, 60)] {
+        file.extend_from_slice(&u32::from_be_bytes(*id).to_le_bytes());
+        file.extend_from_slice(&0u16.to_le_bytes());
+        file.extend_from_slice(&version.to_le_bytes());
+        file.extend_from_slice(&fileofs.to_le_bytes());
+        file.extend_from_slice(&4i32.to_le_bytes());
+    }
+
+    let game_lumps: Vec<_> = parse_game_lumps(
+        &mut LumpReader::new(&file[8..]),
+        &file,
+        file.len(),
+        usize::MAX,
+    )
+    .into_iter()
+    .map(Result::unwrap)
+    .collect();
+
+    assert_eq!(game_lumps.len(), 2);
+    assert_eq!(&game_lumps[0].id, b"sprp");
+    assert_eq!(game_lumps[0].version, 10);
+    assert_eq!(game_lumps[0].data, &file[0..4]);
+    // Past the end of the file
+    assert!(game_lumps[1].data.is_empty());
+}
+
+#[test]
+fn test_compressed_game_lump() {
+    let contents = b"static props, compressed on their own".repeat(4);
+    let mut compressed = vec![];
+    lzma_rs::lzma_compress(&mut &contents[..], &mut compressed).unwrap();
+
+    let mut block = b"LZMA".to_vec();
+    block.extend(&(contents.len() as u32).to_le_bytes());
+    block.extend(&(compressed.len() as u32 - 13).to_le_bytes());
+    block.extend(&compressed[..5]);
+    block.extend(&compressed[13..]);
+
+    // The directory, then the sub-lump, then the end of the game lump
+    let directory_len = 4 + 2 * 16;
+    let block_end = (directory_len + block.len()) as i32;
+    let mut file = 2i32.to_le_bytes().to_vec();
+    for (id, flags, fileofs, filelen) in [
+        (
+            *b"sprp",
+            GAME_LUMP_COMPRESSED,
+            directory_len as i32,
+            contents.len() as i32,
+        ),
+        ([0; 4], 0, block_end, 0),
+    ] {
+        file.extend_from_slice(&u32::from_be_bytes(id).to_le_bytes());
+        file.extend_from_slice(&flags.to_le_bytes());
+        file.extend_from_slice(&0u16.to_le_bytes());
+        file.extend_from_slice(&fileofs.to_le_bytes());
+        file.extend_from_slice(&filelen.to_le_bytes());
+    }
+    file.extend(&block);
+
+    let game_lumps = parse_game_lumps(&mut LumpReader::new(&file), &file, file.len(), usize::MAX);
+
+    assert_eq!(game_lumps.len(), 1);
+    let sprp = game_lumps[0].as_ref().unwrap();
+    assert!(sprp.is_compressed());
+    assert_eq!(sprp.data, contents);
+}
+
+#[test]
+fn test_detail_props() {
+    let mut lump = vec![];
+    lump.extend_from_slice(&1i32.to_le_bytes());
+    let mut name = b"models/grass.mdl".to_vec();
+    name.resize(128, 0);
+    lump.extend_from_slice(&name);
+    lump.extend_from_slice(&0i32.to_le_bytes());
+    lump.extend_from_slice(&1i32.to_le_bytes());
+    for f in [1.0f32, 2.0, 3.0, 0.0, 90.0, 0.0] {
+        lump.extend_from_slice(&f.to_le_bytes());
+    }
+    lump.extend_from_slice(&[
+        0, 0, 7, 0, 10, 20, 30, 0, 0, 0, 0, 0, 1, 2, 3, 4, 1, 0, 0, 0, 0, 0, 0, 0,
+    ]);
+    lump.extend_from_slice(&1.5f32.to_le_bytes());
+
+    let props = DetailProps::from_reader_versioned(&mut LumpReader::new(&lump), 4).unwrap();
+
+    assert_eq!(props.models, ["models/grass.mdl"]);
+    assert_eq!(props.objects.len(), 1);
+    assert_eq!(props.objects[0].origin, (1.0, 2.0, 3.0));
+    assert_eq!(props.objects[0].leaf, 7);
+    assert_eq!(props.objects[0].lighting.g, 20);
+    assert_eq!(props.objects[0].orientation, 1);
+    assert_eq!(props.objects[0].scale, 1.5);
+    assert!(DetailProps::from_reader_versioned(&mut LumpReader::new(&lump), 5).is_none());
+}
+
+#[test]
+fn test_detail_prop_lighting() {
+    let mut dplt = 2i32.to_le_bytes().to_vec();
+    dplt.extend_from_slice(&[10, 20, 30, 0, 1, 40, 50, 60, -1i8 as u8, 2]);
+    let game_lump = |id: &[u8; 4], data: &[u8]| GameLump {
+        id: *id,
+        flags: 0,
+        version: 0,
+        fileofs: 0,
+        filelen: data.len() as i32,
+        data: data.to_vec(),
+    };
+    let mut lumps = ParsedLumps {
+        game_lumps: vec![game_lump(b"dplt", &dplt)],
+        ..Default::default()
+    };
+
+    // There's no HDR lighting, so the LDR is used either way
+    let styles = lumps.detail_prop_lighting(true);
+    assert_eq!(styles, lumps.detail_prop_lighting(false));
+    assert_eq!(styles.len(), 2);
+    assert_eq!(styles[1].lighting.exponent, -1);
+    assert_eq!(styles[1].style, 2);
+
+    // A count running past the data isn't read
+    lumps.game_lumps[0].data.truncate(8);
+    assert!(lumps.detail_prop_lighting(false).is_empty());
+}
+
+#[test]
+fn test_static_props() {
+    // A sprp lump with one model, two leaves and two props of the given size
+    let sprp = |version: u16, size: usize| {
+        let mut lump = 1i32.to_le_bytes().to_vec();
+        let mut name = b"models/props/crate.mdl".to_vec();
+        name.resize(128, 0);
+        lump.extend_from_slice(&name);
+        lump.extend_from_slice(&2i32.to_le_bytes());
+        lump.extend_from_slice(&[4, 0, 9, 0]);
+        lump.extend_from_slice(&2i32.to_le_bytes());
+        for i in 0..2 {
+            let start = lump.len();
+            for f in [i as f32 * 64.0, 0.0, 0.0, 0.0, 90.0, 0.0] {
+                lump.extend_from_slice(&f.to_le_bytes());
+            }
+            // prop_type, first_leaf, leaf_count, solid and flags
+            lump.extend_from_slice(&[0, 0, i, 0, 1, 0, 6, 1]);
+            lump.extend_from_slice(&2i32.to_le_bytes());
+            lump.extend_from_slice(&[0; 20]);
+            if version >= 5 {
+                lump.extend_from_slice(&0.5f32.to_le_bytes());
+            }
+            // Zeroes up to the end of the prop, apart from its last byte
+            lump.resize(start + size, 0);
+            *lump.last_mut().unwrap() = 1;
+            assert_eq!(lump.len() - start, size);
+        }
+        (version, lump)
+    };
+
+    let lumps = ParsedLumps {
+        game_lumps: {
+            let (version, data) = sprp(11, 80);
+            vec![GameLump {
+                id: *b"sprp",
+                flags: 0,
+                version,
+                fileofs: 0,
+                filelen: data.len() as i32,
+                data,
+            }]
+        },
+        ..Default::default()
+    };
+    let data = lumps.static_prop_data().unwrap();
+    assert_eq!(lumps.static_prop_models(), ["models/props/crate.mdl"]);
+    assert_eq!(data.leaves, [4, 9]);
+    assert_eq!(data.props.len(), 2);
+    let prop = &data.props[1];
+    assert_eq!(prop.origin, (64.0, 0.0, 0.0));
+    assert_eq!(prop.angles, (0.0, 90.0, 0.0));
+    assert_eq!(prop.leaves(&data.leaves), [9]);
+    assert_eq!(data.model(prop), Some("models/props/crate.mdl"));
+    assert_eq!((prop.solid, prop.flags, prop.skin), (6, 1, 2));
+    assert_eq!(prop.forced_fade_scale, 0.5);
+    // The uniform scale's last byte, read as a float
+    assert_eq!(prop.uniform_scale, f32::from_le_bytes([0, 0, 0, 1]));
+
+    let (_, v4) = sprp(4, 56);
+    let v4 = StaticProps::from_reader_versioned(&mut LumpReader::new(&v4), 4).unwrap();
+    assert_eq!(v4.props[1].forced_fade_scale, 1.0);
+    assert_eq!(v4.props[1].diffuse_modulation, [255; 4]);
+
+    // Source 2013's version 10 layout ends with the lightmap resolution
+    let (_, tf2) = sprp(10, 72);
+    let tf2 = StaticProps::from_reader_versioned(&mut LumpReader::new(&tf2), 10).unwrap();
+    assert_eq!(tf2.props[1].lightmap_resolution, (0, 256));
+    assert_eq!(tf2.props[1].flags_ex, 0);
+
+    // Props too small for their version
+    let (_, short) = sprp(9, 68);
+    assert!(StaticProps::from_reader_versioned(&mut LumpReader::new(&short), 9).is_none());
+    assert!(StaticProps::from_reader_versioned(&mut LumpReader::new(&short), 3).is_none());
+}
@@ -0,0 +1,226 @@
+use crate::lumps::lump_types::*;
+use crate::lumps::math::*;
+use crate::lumps::LumpParser::{ParsedLumps, TOOL_SURFACES};
+
+use std::collections::HashMap;
+
+/// A face resolved into its vertices along with what's needed to draw or export it
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Polygon {
+    /// Vertices in winding order
+    pub vertices: Vec<Vector>,
+    /// The direction the face faces, zero if its plane is missing
+    pub normal: Vector,
+    /// Material name, if the face's texinfo resolves to one
+    pub material: Option<String>,
+    /// Displacement faces are only the base of the displaced surface, see build_displacement
+    pub is_displacement: bool,
+}
+
+/// A vertex of a render mesh
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MeshVertex {
+    pub position: Vector,
+    pub normal: Vector,
+    /// Texture coordinates, 0 to 1 across the material's texture
+    pub uv: (f32, f32),
+}
+
+impl MeshVertex {
+    /// Compares the exact bits, so vertices are only merged when they're identical
+    fn key(&self) -> [u32; 8] {
+        [
+            self.position.0,
+            self.position.1,
+            self.position.2,
+            self.normal.0,
+            self.normal.1,
+            self.normal.2,
+            self.uv.0,
+            self.uv.1,
+        ]
+        .map(f32::to_bits)
+    }
+}
+
+/// The indices of one face within the index buffer built by build_index_buffer
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FaceRange {
+    pub face_index: usize,
+    pub first_index: usize,
+    pub index_count: usize,
+    pub material: Option<String>,
+}
+
+impl ParsedLumps {
+    /// Returns the texture coordinates of a point on a face, 0 to 1 across its texture
+    pub fn face_uv(&self, face: &Face, point: Vector) -> Option<(f32, f32)> {
+        let texinfo = self.texinfo.get(face.texinfo as usize)?;
+        let texdata = self.texdata.get(texinfo.tex_data as usize)?;
+        if texdata.width <= 0 || texdata.height <= 0 {
+            return None;
+        }
+
+        Some((
+            texinfo.s_axis().project(point) / texdata.width as f32,
+            texinfo.t_axis().project(point) / texdata.height as f32,
+        ))
+    }
+
+    /// Triangulates the visible faces into one vertex and index buffer ready to upload to a GPU.
+    ///
+    /// Faces are ordered by material, so each material's faces make up one run of indices
+    /// that can be drawn together. Vertices with the same position, normal and UV are shared.
+    /// Displacement faces are left out, see build_displacement for their surface.
+    pub fn build_index_buffer(&self) -> (Vec<MeshVertex>, Vec<u32>, Vec<FaceRange>) {
+        let mut faces: Vec<(usize, &Face, Option<&str>)> = self
+            .faces
+            .iter()
+            .enumerate()
+            .filter(|(_, face)| face.displacement_info < 0)
+            .filter(|(_, face)| !self.face_surface_flags(face).intersects(TOOL_SURFACES))
+            .map(|(i, face)| (i, face, self.face_texture_name(face)))
+            .collect();
+        faces.sort_by(|a, b| a.2.cmp(&b.2));
+
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        let mut ranges = vec![];
+        let mut seen = HashMap::new();
+
+        for (face_index, face, material) in faces {
+            let normal = self.face_normal(face).unwrap_or((0.0, 0.0, 0.0));
+            let corners: Vec<u32> = self
+                .face_vertices(face)
+                .iter()
+                .map(|v| {
+                    let position = (v.x, v.y, v.z);
+                    let vertex = MeshVertex {
+                        position,
+                        normal,
+                        uv: self.face_uv(face, position).unwrap_or((0.0, 0.0)),
+                    };
+
+                    *seen.entry(vertex.key()).or_insert_with(|| {
+                        vertices.push(vertex);
+                        vertices.len() as u32 - 1
+                    })
+                })
+                .collect();
+
+            // Faces are convex, so a fan from the first corner covers them
+            let first_index = indices.len();
+            for i in 1..corners.len().saturating_sub(1) {
+                indices.extend_from_slice(&[corners[0], corners[i], corners[i + 1]]);
+            }
+
+            ranges.push(FaceRange {
+                face_index,
+                first_index,
+                index_count: indices.len() - first_index,
+                material: material.map(String::from),
+            });
+        }
+
+        (vertices, indices, ranges)
+    }
+
+    /// Returns the normal of a face's plane, flipped for faces on the back of it
+    pub fn face_normal(&self, face: &Face) -> Option<Vector> {
+        let plane = self.planes.get(face.plane_num as usize)?;
+
+        Some(if face.side != 0 {
+            scale(plane.normal, -1.0)
+        } else {
+            plane.normal
+        })
+    }
+
+    /// Resolves a face's vertices, normal and material together
+    pub fn face_polygon(&self, face: &Face) -> Polygon {
+        Polygon {
+            vertices: self
+                .face_vertices(face)
+                .iter()
+                .map(|v| (v.x, v.y, v.z))
+                .collect(),
+            normal: self.face_normal(face).unwrap_or((0.0, 0.0, 0.0)),
+            material: self.face_texture_name(face).map(String::from),
+            is_displacement: face.displacement_info >= 0,
+        }
+    }
+}
+
+#[test]
+fn test_build_index_buffer() {
+    let face = |first_edge| crate::fixture::face(first_edge, 4);
+    let vertex = |x, y| Vertex { x, y, z: 0.0 };
+    let edge = |a, b| Edge {
+        vertex_indicies: [a, b],
+    };
+
+    // Two quads side by side sharing the edge from vertex 1 to 2
+    let lumps = ParsedLumps {
+        vertex_list: vec![
+            vertex(0.0, 0.0),
+            vertex(1.0, 0.0),
+            vertex(1.0, 1.0),
+            vertex(0.0, 1.0),
+            vertex(2.0, 0.0),
+            vertex(2.0, 1.0),
+        ],
+        edges: vec![
+            edge(0, 0),
+            edge(0, 1),
+            edge(1, 2),
+            edge(2, 3),
+            edge(3, 0),
+            edge(1, 4),
+            edge(4, 5),
+            edge(5, 2),
+        ],
+        surfedges: vec![1, 2, 3, 4, 5, 6, 7, -2],
+        planes: vec![Plane {
+            normal: (0.0, 0.0, 1.0),
+            dist_from_origin: 0.0,
+            r#type: 2,
+        }],
+        faces: vec![face(0), face(4)],
+        ..Default::default()
+    };
+
+    let (vertices, indices, ranges) = lumps.build_index_buffer();
+
+    assert_eq!(vertices.len(), 6);
+    assert_eq!(indices.len(), 12);
+    assert_eq!(ranges.len(), 2);
+    assert_eq!(ranges[1].first_index, 6);
+    assert_eq!(ranges[1].index_count, 6);
+    assert!(vertices.iter().all(|v| v.normal == (0.0, 0.0, 1.0)));
+    assert_eq!(&indices[0..3], [0, 1, 2]);
+}
+
+#[test]
+fn test_face_normal() {
+    let face = |plane_num, side| Face {
+        plane_num,
+        side,
+        ..crate::fixture::face(0, 0)
+    };
+    let lumps = ParsedLumps {
+        planes: vec![Plane {
+            normal: (0.0, 1.0, 0.0),
+            dist_from_origin: 32.0,
+            r#type: 1,
+        }],
+        ..Default::default()
+    };
+
+    assert_eq!(lumps.face_normal(&face(0, 0)), Some((0.0, 1.0, 0.0)));
+    assert_eq!(lumps.face_normal(&face(0, 1)), Some((0.0, -1.0, 0.0)));
+    assert_eq!(lumps.face_normal(&face(1, 0)), None);
+    assert_eq!(lumps.face_polygon(&face(1, 1)).normal, (0.0, 0.0, 0.0));
+}
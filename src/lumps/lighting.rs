@@ -0,0 +1,368 @@
+use crate::lumps::lump_types::*;
+use crate::lumps::math::*;
+use crate::lumps::LumpParser::ParsedLumps;
+use crate::lumps::{clamped_slice, LumpType};
+
+use std::collections::HashMap;
+
+/// Marks an unused slot in Face::styles
+pub const UNUSED_STYLE: u8 = 0xFF;
+
+/// Bump mapped faces store the flat lightmap followed by one per bump basis vector
+const BUMP_LIGHTMAPS: usize = 4;
+
+/// The lightmap of a face for one of its light styles
+#[derive(Debug)]
+pub struct LightmapImage<'a> {
+    /// The light style, 0 being the normal static lighting
+    pub style: u8,
+    pub width: usize,
+    pub height: usize,
+    /// Samples row by row, width * height of them
+    pub samples: &'a [LightmapSample],
+}
+
+impl ParsedLumps {
+    /// True if the map has HDR lightmaps. This checks the LightingHDR lump rather than
+    /// the MapFlags lump, whose flag doesn't always agree with what the map contains.
+    pub fn has_hdr_lighting(&self) -> bool {
+        self.has_lump_data(LumpType::LightingHDR)
+    }
+
+    /// True if the map has LDR lightmaps, which it may not if it was compiled for HDR only
+    pub fn has_ldr_lighting(&self) -> bool {
+        self.has_lump_data(LumpType::Lighting)
+    }
+
+    fn has_lump_data(&self, lump_type: LumpType) -> bool {
+        self.lump_meta
+            .get(lump_type as usize)
+            .is_some_and(|meta| meta.present && meta.length > 0)
+    }
+
+    /// Counts the luxels stored for a face: one block per light style,
+    /// and four of them per style on bump mapped faces
+    pub fn face_luxel_count(&self, face: &Face) -> u64 {
+        let size = face.lightmap_texture_size_in_luxels;
+        if face.light_offset < 0 || size[0] < 0 || size[1] < 0 {
+            return 0;
+        }
+
+        let mut block = (size[0] as u64 + 1) * (size[1] as u64 + 1);
+        if self
+            .face_surface_flags(face)
+            .contains(SurfaceFlags::BUMPLIGHT)
+        {
+            block *= BUMP_LIGHTMAPS as u64;
+        }
+
+        let styles = face
+            .styles
+            .iter()
+            .take_while(|&&s| s != UNUSED_STYLE)
+            .count();
+        block * styles as u64
+    }
+
+    /// Total luxels of lightmap data used by every face, each taking 4 bytes
+    pub fn lightmap_luxel_count(&self) -> u64 {
+        self.faces
+            .iter()
+            .map(|face| self.face_luxel_count(face))
+            .sum()
+    }
+
+    /// Sums the luxels of the faces using each material, to find ones with too fine a lightmap scale.
+    /// Faces whose material can't be resolved are grouped under an empty name.
+    pub fn lightmap_luxels_by_material(&self) -> HashMap<String, u64> {
+        let mut luxels = HashMap::new();
+
+        for face in &self.faces {
+            let count = self.face_luxel_count(face);
+            if count > 0 {
+                let name = self.face_texture_name(face).unwrap_or("");
+                *luxels.entry(name.to_string()).or_insert(0) += count;
+            }
+        }
+
+        luxels
+    }
+
+    /// Returns the ambient lighting samples of a leaf, from the HDR or LDR lumps.
+    /// Maps compiled with only one set of lighting fall back to it whichever is asked for,
+    /// so a renderer should ask for the same set it uses for face lightmaps.
+    ///
+    /// Version 0 leaves store their ambient lighting in `Leaf::ambient_lighting` instead.
+    pub fn leaf_ambient_samples(&self, leaf_index: usize, hdr: bool) -> &[LeafAmbientLighting] {
+        let hdr_present = !self.leaf_ambient_index_hdr.is_empty();
+        let ldr_present = !self.leaf_ambient_index.is_empty();
+
+        // The index and lighting lumps must come from the same set
+        let (index, lighting) = if (hdr && hdr_present) || !ldr_present {
+            (
+                &self.leaf_ambient_index_hdr,
+                &self.leaf_ambient_lighting_hdr,
+            )
+        } else {
+            (&self.leaf_ambient_index, &self.leaf_ambient_lighting)
+        };
+
+        index.get(leaf_index).map_or(&[], |index| {
+            clamped_slice(
+                lighting,
+                index.first_ambient_sample as i64,
+                index.ambient_sample_count as i64,
+            )
+        })
+    }
+
+    /// Returns the lightmaps of a face, one for each light style it uses.
+    /// Bump mapped faces only return their flat lightmap per style.
+    pub fn face_lightmap(&self, face: &Face) -> Vec<LightmapImage<'_>> {
+        let mut out = vec![];
+
+        let size = face.lightmap_texture_size_in_luxels;
+        if face.light_offset < 0 || size[0] < 0 || size[1] < 0 {
+            return out; // The face isn't lit
+        }
+
+        let width = size[0] as usize + 1;
+        let height = size[1] as usize + 1;
+        let mut block = width * height;
+        if self
+            .face_surface_flags(face)
+            .contains(SurfaceFlags::BUMPLIGHT)
+        {
+            block *= BUMP_LIGHTMAPS;
+        }
+
+        // light_offset is in bytes, each sample is 4
+        let mut start = face.light_offset as usize / 4;
+
+        for &style in &face.styles {
+            if style == UNUSED_STYLE {
+                break;
+            }
+
+            let samples = match self.lightmap_samples.get(start..start + width * height) {
+                Some(samples) => samples,
+                None => break,
+            };
+
+            out.push(LightmapImage {
+                style,
+                width,
+                height,
+                samples,
+            });
+
+            start += block;
+        }
+
+        out
+    }
+
+    /// Finds the cubemap closest to the centre of a face, which is the one the engine
+    /// assigns to its env mapped materials when the map loads. None if the map has no
+    /// cubemaps or the face has no vertices.
+    pub fn nearest_cubemap(&self, face: &Face) -> Option<&Cubemap> {
+        let vertices = self.face_vertices(face);
+        if vertices.is_empty() {
+            return None;
+        }
+
+        let sum = vertices.iter().fold((0.0, 0.0, 0.0), |sum, vertex| {
+            add(sum, (vertex.x, vertex.y, vertex.z))
+        });
+        let center = scale(sum, 1.0 / vertices.len() as f32);
+
+        self.cubemaps.iter().min_by(|a, b| {
+            let a = length(sub(a.origin_vector(), center));
+            let b = length(sub(b.origin_vector(), center));
+            a.total_cmp(&b)
+        })
+    }
+}
+
+#[test]
+fn test_leaf_ambient_samples() {
+    let sample = |r| LeafAmbientLighting {
+        cube: [(); 6].map(|_| LightmapSample {
+            r,
+            g: 0,
+            b: 0,
+            exponent: 0,
+        }),
+        x: 128,
+        y: 128,
+        z: 128,
+    };
+    let index = |first_ambient_sample, ambient_sample_count| LeafAmbientIndex {
+        ambient_sample_count,
+        first_ambient_sample,
+    };
+
+    let mut lumps = ParsedLumps {
+        leaf_ambient_index_hdr: vec![index(0, 1), index(1, 2)],
+        leaf_ambient_lighting_hdr: vec![sample(1), sample(2), sample(3)],
+        ..Default::default()
+    };
+
+    // Only HDR is present, so LDR falls back to it
+    assert_eq!(lumps.leaf_ambient_samples(1, false).len(), 2);
+    assert_eq!(lumps.leaf_ambient_samples(1, true)[1].cube[0].r, 3);
+    assert!(lumps.leaf_ambient_samples(2, true).is_empty());
+
+    lumps.leaf_ambient_index = vec![index(0, 1)];
+    lumps.leaf_ambient_lighting = vec![sample(10)];
+    assert_eq!(lumps.leaf_ambient_samples(0, false)[0].cube[0].r, 10);
+    assert_eq!(lumps.leaf_ambient_samples(0, true)[0].cube[0].r, 1);
+}
+
+#[test]
+fn test_lightmap_luxel_count() {
+    let face = |light_offset, styles| Face {
+        styles,
+        light_offset,
+        lightmap_texture_size_in_luxels: [3, 1],
+        ..crate::fixture::face(0, 0)
+    };
+
+    let lumps = ParsedLumps {
+        faces: vec![
+            face(0, [0, UNUSED_STYLE, UNUSED_STYLE, UNUSED_STYLE]),
+            face(32, [0, 32, UNUSED_STYLE, UNUSED_STYLE]),
+            // Unlit
+            face(-1, [0, UNUSED_STYLE, UNUSED_STYLE, UNUSED_STYLE]),
+        ],
+        ..Default::default()
+    };
+
+    assert_eq!(lumps.face_luxel_count(&lumps.faces[1]), 16);
+    assert_eq!(lumps.lightmap_luxel_count(), 24);
+    assert_eq!(lumps.lightmap_luxels_by_material()[""], 24);
+}
+
+#[test]
+fn test_face_lightmap() {
+    // 2x1 luxels, with each sample's red value set to its index
+    let face = |light_offset, texinfo, styles| Face {
+        light_offset,
+        texinfo,
+        styles,
+        lightmap_texture_size_in_luxels: [1, 0],
+        ..crate::fixture::face(0, 0)
+    };
+    let lumps = ParsedLumps {
+        texinfo: vec![TexInfo {
+            texture_vecs: [[0.0; 4]; 2],
+            lightmap_vecs: [[0.0; 4]; 2],
+            flags: SurfaceFlags::BUMPLIGHT.0,
+            tex_data: -1,
+        }],
+        lightmap_samples: (0..16)
+            .map(|r| LightmapSample {
+                r,
+                g: 0,
+                b: 0,
+                exponent: 0,
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let starts = |face: &Face| -> Vec<(u8, u8)> {
+        lumps
+            .face_lightmap(face)
+            .iter()
+            .map(|image| (image.style, image.samples[0].r))
+            .collect()
+    };
+
+    // Each style follows the last one's 2 luxels
+    let flat = face(0, -1, [0, 5, UNUSED_STYLE, UNUSED_STYLE]);
+    assert_eq!(starts(&flat), [(0, 0), (5, 2)]);
+    let image = &lumps.face_lightmap(&flat)[1];
+    assert_eq!((image.width, image.height, image.samples.len()), (2, 1, 2));
+
+    // Bump mapped faces store 4 lightmaps per style, only the flat one is returned
+    let bumped = face(16, 0, [0, 6, UNUSED_STYLE, UNUSED_STYLE]);
+    assert_eq!(starts(&bumped), [(0, 4), (6, 12)]);
+
+    // Styles after the first unused slot are ignored
+    let ended = face(0, -1, [0, UNUSED_STYLE, 3, 4]);
+    assert_eq!(starts(&ended), [(0, 0)]);
+
+    assert!(lumps.face_lightmap(&face(-1, -1, [0, 1, 2, 3])).is_empty());
+}
+
+#[test]
+fn test_has_lighting() {
+    use crate::fixture::FixtureBuilder;
+    use crate::lumps::LumpParser::parse_lump_data;
+    use crate::BspParser;
+
+    let parser = BspParser {
+        data: FixtureBuilder::new()
+            .lump(LumpType::Lighting, vec![0; 8])
+            // In the directory but empty
+            .lump(LumpType::LightingHDR, vec![])
+            .build()
+            .into(),
+    };
+    let lumps = parse_lump_data(parser.read_lump_info().unwrap(), &parser.data);
+
+    assert!(lumps.has_ldr_lighting());
+    assert!(!lumps.has_hdr_lighting());
+}
+
+#[test]
+fn test_nearest_cubemap() {
+    use crate::fixture::FixtureBuilder;
+    use crate::lumps::LumpParser::parse_lump_data;
+    use crate::BspParser;
+
+    let cubemap_lump = [[0, 0, 0, 0], [128, 128, 64, 6]]
+        .iter()
+        .flatten()
+        .flat_map(|i: &i32| i.to_le_bytes())
+        .collect();
+    let parser = BspParser {
+        data: FixtureBuilder::new()
+            .vertices(&[
+                (96.0, 96.0, 64.0),
+                (160.0, 96.0, 64.0),
+                (160.0, 160.0, 64.0),
+                (96.0, 160.0, 64.0),
+            ])
+            .lump(LumpType::Cubemaps, cubemap_lump)
+            .build()
+            .into(),
+    };
+    let mut lumps = parse_lump_data(parser.read_lump_info().unwrap(), &parser.data);
+    assert_eq!(lumps.cubemaps.len(), 2);
+    assert_eq!(lumps.cubemaps[1].size, 6);
+    assert_eq!(lumps.cubemaps[0].resolution(), 32);
+    assert_eq!(lumps.cubemaps[1].resolution(), 32);
+    assert_eq!(
+        Cubemap {
+            size: 8,
+            ..lumps.cubemaps[1].clone()
+        }
+        .resolution(),
+        128
+    );
+
+    lumps.edges = (0..4)
+        .map(|i| Edge {
+            vertex_indicies: [i, (i + 1) % 4],
+        })
+        .collect();
+    lumps.surfedges = vec![0, 1, 2, 3];
+    let face = crate::fixture::face(0, 4);
+
+    // The face is centred on the second cubemap
+    assert_eq!(lumps.nearest_cubemap(&face), Some(&lumps.cubemaps[1]));
+
+    lumps.cubemaps.clear();
+    assert_eq!(lumps.nearest_cubemap(&face), None);
+}
@@ -1,23 +1,54 @@
 use crate::lumps::LumpReader;
-use regex::Regex;
 
 use std::collections::HashMap;
 
 pub trait BspParseable {
+    /// The fewest bytes one element takes up, so a count read from a lump can be checked
+    /// against the bytes left before reading that many, and a lump ending partway through
+    /// an element is reported rather than read past
+    const MIN_SIZE: usize = 1;
+
     fn from_reader(data: &mut LumpReader) -> Self;
 }
 
+impl BspParseable for i32 {
+    const MIN_SIZE: usize = 4;
+
+    fn from_reader(data: &mut LumpReader) -> Self {
+        data.read_i32()
+    }
+}
+
+impl BspParseable for u32 {
+    const MIN_SIZE: usize = 4;
+
+    fn from_reader(data: &mut LumpReader) -> Self {
+        data.read_u32()
+    }
+}
+
+impl BspParseable for u16 {
+    const MIN_SIZE: usize = 2;
+
+    fn from_reader(data: &mut LumpReader) -> Self {
+        data.read_u16()
+    }
+}
+
 pub type Entity = HashMap<String, String>;
 
-type Vector = (f32, f32, f32);
+pub type Vector = (f32, f32, f32);
 
 impl BspParseable for Vector {
+    const MIN_SIZE: usize = 12;
+
     fn from_reader(data: &mut LumpReader) -> Self {
-        (data.read_f32(), data.read_f32(), data.read_f32())
+        data.read_vec3()
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Plane {
     /// Normal vector
     pub normal: Vector,
@@ -27,10 +58,20 @@ pub struct Plane {
     pub r#type: i32,
 }
 
+impl Plane {
+    /// Signed distance from the plane to a point, positive on the side the normal faces
+    pub fn distance_to(&self, point: Vector) -> f32 {
+        self.normal.0 * point.0 + self.normal.1 * point.1 + self.normal.2 * point.2
+            - self.dist_from_origin
+    }
+}
+
 impl BspParseable for Plane {
+    const MIN_SIZE: usize = 20;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
-            normal: Vector::from_reader(data),
+            normal: data.read_vec3(),
 
             dist_from_origin: data.read_f32(),
             r#type: data.read_i32(),
@@ -38,7 +79,8 @@ impl BspParseable for Plane {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TexData {
     /// RGB Reflectivity
     pub reflectivity: Vector,
@@ -55,9 +97,11 @@ pub struct TexData {
 }
 
 impl BspParseable for TexData {
+    const MIN_SIZE: usize = 32;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
-            reflectivity: (data.read_f32(), data.read_f32(), data.read_f32()),
+            reflectivity: data.read_vec3(),
 
             texdata_string_table_index: data.read_i32(),
 
@@ -69,7 +113,8 @@ impl BspParseable for TexData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vertex {
     /// X coordinate
     pub x: f32,
@@ -80,6 +125,8 @@ pub struct Vertex {
 }
 
 impl BspParseable for Vertex {
+    const MIN_SIZE: usize = 12;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
             x: data.read_f32(),
@@ -89,7 +136,8 @@ impl BspParseable for Vertex {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Node {
     /// Index into the plane array
     pub plane_num: i32,
@@ -99,7 +147,8 @@ pub struct Node {
     pub mins: [i16; 3],
     /// For frustrum culling
     pub maxs: [i16; 3],
-    /// Index into the face array
+    /// Index into the face array. Unsigned 16 bits in every shipped Nodes lump version,
+    /// which is why the engine caps maps at 65536 faces.
     pub first_face: u16,
     /// Number of faces (counting both sides)
     pub num_faces: u16,
@@ -110,12 +159,14 @@ pub struct Node {
 }
 
 impl BspParseable for Node {
+    const MIN_SIZE: usize = 32;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
             plane_num: data.read_i32(),
             children: [data.read_i32(), data.read_i32()],
-            mins: [data.read_i16(), data.read_i16(), data.read_i16()],
-            maxs: [data.read_i16(), data.read_i16(), data.read_i16()],
+            mins: data.read_vec3i16(),
+            maxs: data.read_vec3i16(),
             first_face: data.read_u16(),
             num_faces: data.read_u16(),
             area: data.read_i16(),
@@ -124,7 +175,41 @@ impl BspParseable for Node {
     }
 }
 
-#[derive(Debug)]
+/// A child of a node in the BSP tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NodeChild {
+    /// Index into the node array
+    Node(u32),
+    /// Index into the leaf array
+    Leaf(u32),
+}
+
+/// Converts a bounding box stored as shorts to floats
+fn box_to_f32(mins: [i16; 3], maxs: [i16; 3]) -> ([f32; 3], [f32; 3]) {
+    (mins.map(f32::from), maxs.map(f32::from))
+}
+
+impl Node {
+    /// The bounding box of everything below the node, as mins and maxs
+    pub fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        box_to_f32(self.mins, self.maxs)
+    }
+
+    /// Decodes one of the node's children, side 0 being in front of the plane and 1 behind it
+    pub fn child(&self, side: usize) -> NodeChild {
+        let child = self.children[side];
+        if child >= 0 {
+            NodeChild::Node(child as u32)
+        } else {
+            // Leaves are stored as -(leaf + 1) so that leaf 0 is still negative
+            NodeChild::Leaf((-(child + 1)) as u32)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TexInfo {
     /// [s/t]/[xyz offset]
     pub texture_vecs: [[f32; 4]; 2],
@@ -137,43 +222,198 @@ pub struct TexInfo {
 }
 
 impl BspParseable for TexInfo {
+    const MIN_SIZE: usize = 72;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
-            texture_vecs: [
-                [
-                    data.read_f32(),
-                    data.read_f32(),
-                    data.read_f32(),
-                    data.read_f32(),
-                ],
-                [
-                    data.read_f32(),
-                    data.read_f32(),
-                    data.read_f32(),
-                    data.read_f32(),
-                ],
-            ],
-            lightmap_vecs: [
-                [
-                    data.read_f32(),
-                    data.read_f32(),
-                    data.read_f32(),
-                    data.read_f32(),
-                ],
-                [
-                    data.read_f32(),
-                    data.read_f32(),
-                    data.read_f32(),
-                    data.read_f32(),
-                ],
-            ],
+            texture_vecs: [data.read_vec4f32(), data.read_vec4f32()],
+            lightmap_vecs: [data.read_vec4f32(), data.read_vec4f32()],
             flags: data.read_i32(),
             tex_data: data.read_i32(),
         }
     }
 }
 
-#[derive(Debug)]
+/// Flags describing how a surface is drawn, stored in TexInfo::flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SurfaceFlags(pub i32);
+
+impl SurfaceFlags {
+    /// Value will hold the light strength
+    pub const LIGHT: Self = Self(0x1);
+    /// Don't draw, indicates we should skylight + draw 2d sky but not draw the 3D skybox
+    pub const SKY2D: Self = Self(0x2);
+    /// Don't draw, but add to skybox
+    pub const SKY: Self = Self(0x4);
+    /// Turbulent water warp
+    pub const WARP: Self = Self(0x8);
+    /// Translucent
+    pub const TRANS: Self = Self(0x10);
+    /// The surface can not have a portal placed on it
+    pub const NOPORTAL: Self = Self(0x20);
+    /// Xbox hack to work around elimination of trigger surfaces
+    pub const TRIGGER: Self = Self(0x40);
+    /// Don't bother referencing the texture
+    pub const NODRAW: Self = Self(0x80);
+    /// Make a primary bsp splitter
+    pub const HINT: Self = Self(0x100);
+    /// Completely ignore, allowing non-closed brushes
+    pub const SKIP: Self = Self(0x200);
+    /// Don't calculate light
+    pub const NOLIGHT: Self = Self(0x400);
+    /// Calculate three lightmaps, so normal mapping works
+    pub const BUMPLIGHT: Self = Self(0x800);
+    /// Don't receive shadows
+    pub const NOSHADOWS: Self = Self(0x1000);
+    /// Don't receive decals
+    pub const NODECALS: Self = Self(0x2000);
+    /// Don't subdivide patches on this surface
+    pub const NOCHOP: Self = Self(0x4000);
+    /// Surface is part of a hitbox
+    pub const HITBOX: Self = Self(0x8000);
+
+    /// True if every flag in other is set
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// True if any flag in other is set
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl std::ops::BitOr for SurfaceFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Flags describing what a leaf or brush is filled with, stored in Leaf::contents and Brush::contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ContentsFlags(pub i32);
+
+impl ContentsFlags {
+    /// No contents
+    pub const EMPTY: Self = Self(0x0);
+    /// An eye is never valid in a solid
+    pub const SOLID: Self = Self(0x1);
+    /// Translucent, but not watery (glass)
+    pub const WINDOW: Self = Self(0x2);
+    pub const AUX: Self = Self(0x4);
+    /// Alpha-tested "grate" textures. Bullets and sight pass through, but solids don't
+    pub const GRATE: Self = Self(0x8);
+    pub const SLIME: Self = Self(0x10);
+    pub const WATER: Self = Self(0x20);
+    /// Blocks line of sight, but not shots
+    pub const BLOCKLOS: Self = Self(0x40);
+    /// Things that can't be seen through (may be non-solid though)
+    pub const OPAQUE: Self = Self(0x80);
+    pub const TESTFOGVOLUME: Self = Self(0x100);
+    /// Blocks light from the sun and sky
+    pub const BLOCKLIGHT: Self = Self(0x400);
+    pub const TEAM1: Self = Self(0x800);
+    pub const TEAM2: Self = Self(0x1000);
+    /// Ignore CONTENTS_OPAQUE on surfaces that have SURF_NODRAW
+    pub const IGNORE_NODRAW_OPAQUE: Self = Self(0x2000);
+    /// Hits entities which are MOVETYPE_PUSH (doors, plats, etc.)
+    pub const MOVEABLE: Self = Self(0x4000);
+    /// Remaining contents are non-visible, and don't eat brushes
+    pub const AREAPORTAL: Self = Self(0x8000);
+    pub const PLAYERCLIP: Self = Self(0x10000);
+    pub const MONSTERCLIP: Self = Self(0x20000);
+    pub const CURRENT_0: Self = Self(0x40000);
+    pub const CURRENT_90: Self = Self(0x80000);
+    pub const CURRENT_180: Self = Self(0x100000);
+    pub const CURRENT_270: Self = Self(0x200000);
+    pub const CURRENT_UP: Self = Self(0x400000);
+    pub const CURRENT_DOWN: Self = Self(0x800000);
+    /// Removed before bsping an entity
+    pub const ORIGIN: Self = Self(0x1000000);
+    /// Should never be on a brush, only in game
+    pub const MONSTER: Self = Self(0x2000000);
+    pub const DEBRIS: Self = Self(0x4000000);
+    /// Brushes to be added after vis leafs
+    pub const DETAIL: Self = Self(0x8000000);
+    /// Auto set if any surface has trans
+    pub const TRANSLUCENT: Self = Self(0x10000000);
+    pub const LADDER: Self = Self(0x20000000);
+    /// Use accurate hitboxes on trace
+    pub const HITBOX: Self = Self(0x40000000);
+
+    /// True if every flag in other is set
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// True if any flag in other is set
+    pub fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl std::ops::BitOr for ContentsFlags {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// One axis of a texture projection. A point's coordinate along it is dot(axis, point) + offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TexAxis {
+    pub axis: Vector,
+    pub offset: f32,
+}
+
+impl TexAxis {
+    fn from_vec(vec: &[f32; 4]) -> Self {
+        TexAxis {
+            axis: (vec[0], vec[1], vec[2]),
+            offset: vec[3],
+        }
+    }
+
+    /// Projects a point onto the axis, giving its texture coordinate in texels or luxels
+    pub fn project(&self, point: Vector) -> f32 {
+        self.axis.0 * point.0 + self.axis.1 * point.1 + self.axis.2 * point.2 + self.offset
+    }
+}
+
+impl TexInfo {
+    /// The texture's horizontal axis
+    pub fn s_axis(&self) -> TexAxis {
+        TexAxis::from_vec(&self.texture_vecs[0])
+    }
+
+    /// The texture's vertical axis
+    pub fn t_axis(&self) -> TexAxis {
+        TexAxis::from_vec(&self.texture_vecs[1])
+    }
+
+    /// The lightmap's horizontal axis
+    pub fn lightmap_s_axis(&self) -> TexAxis {
+        TexAxis::from_vec(&self.lightmap_vecs[0])
+    }
+
+    /// The lightmap's vertical axis
+    pub fn lightmap_t_axis(&self) -> TexAxis {
+        TexAxis::from_vec(&self.lightmap_vecs[1])
+    }
+
+    pub fn surface_flags(&self) -> SurfaceFlags {
+        SurfaceFlags(self.flags)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Face {
     /// The plane number
     pub plane_num: u16,
@@ -212,6 +452,8 @@ pub struct Face {
 }
 
 impl BspParseable for Face {
+    const MIN_SIZE: usize = 56;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
             plane_num: data.read_u16(),
@@ -244,7 +486,8 @@ impl BspParseable for Face {
 /// R: R * 2^(exponent)
 /// G: G * 2^(exponent)
 /// B: B * 2^(exponent)
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LightmapSample {
     /// Red value
     pub r: u8,
@@ -257,6 +500,8 @@ pub struct LightmapSample {
 }
 
 impl BspParseable for LightmapSample {
+    const MIN_SIZE: usize = 4;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
             r: data.read_u8(),
@@ -267,7 +512,8 @@ impl BspParseable for LightmapSample {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OccluderData {
     pub flags: i32,
     /// Index into OccluderPolyData
@@ -282,6 +528,8 @@ pub struct OccluderData {
 }
 
 impl BspParseable for OccluderData {
+    const MIN_SIZE: usize = 40;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
             flags: data.read_i32(),
@@ -294,7 +542,8 @@ impl BspParseable for OccluderData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OccluderPolyData {
     /// Index into occluder vertex indicies
     pub first_vertex_index: i32,
@@ -305,6 +554,8 @@ pub struct OccluderPolyData {
 }
 
 impl BspParseable for OccluderPolyData {
+    const MIN_SIZE: usize = 12;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
             first_vertex_index: data.read_i32(),
@@ -314,7 +565,8 @@ impl BspParseable for OccluderPolyData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Occluder {
     pub count: i32,
     pub occluder_data: Vec<OccluderData>,
@@ -325,25 +577,15 @@ pub struct Occluder {
 }
 
 impl BspParseable for Occluder {
+    const MIN_SIZE: usize = 12;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         let count = data.read_i32();
-        let mut occluder_data = vec![];
-
-        for _ in 0..count {
-            occluder_data.push(OccluderData::from_reader(data));
-        }
+        let occluder_data = data.read_array(count.max(0) as usize);
         let poly_data_count = data.read_i32();
-        let mut poly_data = vec![];
-
-        for _ in 0..poly_data_count {
-            poly_data.push(OccluderPolyData::from_reader(data));
-        }
+        let poly_data = data.read_array(poly_data_count.max(0) as usize);
         let vertex_index_count = data.read_i32();
-        let mut vertex_indicies = vec![];
-
-        for _ in 0..vertex_index_count {
-            vertex_indicies.push(data.read_i32())
-        }
+        let vertex_indicies = data.read_array(vertex_index_count.max(0) as usize);
 
         Occluder {
             count,
@@ -356,13 +598,202 @@ impl BspParseable for Occluder {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Leaf {
+    /// Contents flags of the leaf
+    pub contents: i32,
+    /// Visibility cluster, -1 if the leaf isn't in one
+    pub cluster: i16,
+    /// Area (lower 9 bits) and flags (upper 7 bits)
+    pub area_flags: i16,
+    /// For frustrum culling
+    pub mins: [i16; 3],
+    /// For frustrum culling
+    pub maxs: [i16; 3],
+    /// Index into leaf_faces. Like the Leaffaces and Leafbrushes lumps themselves, the leaf
+    /// indices are 16 bits in both Leafs lump versions, there's no wider layout to read.
+    pub first_leaf_face: u16,
+    /// Number of leaf faces
+    pub num_leaf_faces: u16,
+    /// Index into leaf_brushes
+    pub first_leaf_brush: u16,
+    /// Number of leaf brushes
+    pub num_leaf_brushes: u16,
+    /// Index into the leaf water data, -1 if the leaf isn't in water
+    pub leaf_water_data_id: i16,
+    /// Ambient light arriving from +x, -x, +y, -y, +z and -z. Only stored in version 0 leaves,
+    /// later versions keep it in the LeafAmbientLighting lumps.
+    pub ambient_lighting: Option<[LightmapSample; 6]>,
+}
+
+impl Leaf {
+    /// Reads a leaf laid out for the given Leafs lump version.
+    /// Version 0 leaves end with an ambient lighting cube that version 1 moved to its own lump.
+    pub fn from_reader_versioned(data: &mut LumpReader, version: i32) -> Self {
+        let start = data.get_pos();
+
+        let mut leaf = Self {
+            contents: data.read_i32(),
+            cluster: data.read_i16(),
+            area_flags: data.read_i16(),
+            mins: data.read_vec3i16(),
+            maxs: data.read_vec3i16(),
+            first_leaf_face: data.read_u16(),
+            num_leaf_faces: data.read_u16(),
+            first_leaf_brush: data.read_u16(),
+            num_leaf_brushes: data.read_u16(),
+            leaf_water_data_id: data.read_i16(),
+            ambient_lighting: None,
+        };
+
+        if version == 0 {
+            leaf.ambient_lighting = Some([
+                LightmapSample::from_reader(data),
+                LightmapSample::from_reader(data),
+                LightmapSample::from_reader(data),
+                LightmapSample::from_reader(data),
+                LightmapSample::from_reader(data),
+                LightmapSample::from_reader(data),
+            ]);
+        }
+        data.skip_bytes(2); // Pad to a multiple of 4 bytes
+
+        // A wrong stride would misread every leaf after this one
+        debug_assert_eq!(data.get_pos() - start, Self::size(version));
+
+        leaf
+    }
+
+    /// The layout version of a map's leaves. Maps before version 20 always store the
+    /// ambient lighting cube in each leaf, whatever the lump claims, as the separate
+    /// ambient lighting lumps were added in version 20. Later maps go by the lump version.
+    pub fn layout_version(map_version: i32, lump_version: i32) -> i32 {
+        if map_version < 20 {
+            0
+        } else {
+            lump_version
+        }
+    }
+
+    /// Size in bytes of a leaf in the given Leafs lump version
+    pub fn size(version: i32) -> usize {
+        if version == 0 {
+            56
+        } else {
+            32
+        }
+    }
+}
+
+impl Leaf {
+    /// Set in flags() when the 3D skybox is visible from the leaf
+    pub const FLAGS_SKY: u8 = 0x01;
+    /// Set in flags() when the leaf uses radial light sampling for ambient lighting
+    pub const FLAGS_RADIAL: u8 = 0x02;
+    /// Set in flags() when the 2D skybox is visible from the leaf
+    pub const FLAGS_SKY2D: u8 = 0x04;
+
+    /// The visibility cluster, None for the -1 used by solid leaves that aren't in one
+    pub fn cluster_opt(&self) -> Option<u16> {
+        if self.cluster < 0 {
+            None
+        } else {
+            Some(self.cluster as u16)
+        }
+    }
+
+    /// The area the leaf is in, the low 9 bits of area_flags in every leaf version
+    pub fn area(&self) -> u16 {
+        self.area_flags as u16 & 0x1FF
+    }
+
+    /// The leaf's flags, the high 7 bits of area_flags
+    pub fn flags(&self) -> u8 {
+        (self.area_flags as u16 >> 9) as u8
+    }
+
+    /// The bounding box of the leaf, as mins and maxs
+    pub fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        box_to_f32(self.mins, self.maxs)
+    }
+
+    pub fn contents_flags(&self) -> ContentsFlags {
+        ContentsFlags(self.contents)
+    }
+}
+
+impl BspParseable for Leaf {
+    fn from_reader(data: &mut LumpReader) -> Self {
+        Self::from_reader_versioned(data, 1)
+    }
+}
+
+/// The range of ambient samples belonging to a leaf, indexed by leaf
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LeafAmbientIndex {
+    pub ambient_sample_count: u16,
+    /// Index into the matching LeafAmbientLighting lump
+    pub first_ambient_sample: u16,
+}
+
+impl BspParseable for LeafAmbientIndex {
+    const MIN_SIZE: usize = 4;
+
+    fn from_reader(data: &mut LumpReader) -> Self {
+        Self {
+            ambient_sample_count: data.read_u16(),
+            first_ambient_sample: data.read_u16(),
+        }
+    }
+}
+
+/// Ambient lighting sampled at a point within a leaf, used to light models
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LeafAmbientLighting {
+    /// Light arriving from +x, -x, +y, -y, +z and -z
+    pub cube: [LightmapSample; 6],
+    /// Position within the leaf's bounds, 0 being the mins and 255 the maxs
+    pub x: u8,
+    pub y: u8,
+    pub z: u8,
+}
+
+impl BspParseable for LeafAmbientLighting {
+    const MIN_SIZE: usize = 28;
+
+    fn from_reader(data: &mut LumpReader) -> Self {
+        let cube = [
+            LightmapSample::from_reader(data),
+            LightmapSample::from_reader(data),
+            LightmapSample::from_reader(data),
+            LightmapSample::from_reader(data),
+            LightmapSample::from_reader(data),
+            LightmapSample::from_reader(data),
+        ];
+        let out = Self {
+            cube,
+            x: data.read_u8(),
+            y: data.read_u8(),
+            z: data.read_u8(),
+        };
+        data.skip_bytes(1); // Padding
+        out
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Edge {
     /// Vertex indicies
-    vertex_indicies: [u16; 2],
+    pub vertex_indicies: [u16; 2],
 }
 
 impl BspParseable for Edge {
+    const MIN_SIZE: usize = 4;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
             vertex_indicies: [data.read_u16(), data.read_u16()],
@@ -370,28 +801,31 @@ impl BspParseable for Edge {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Model {
     /// Bounding box
-    mins: Vector,
+    pub mins: Vector,
     /// Bounding box
-    maxs: Vector,
+    pub maxs: Vector,
     /// For sounds/lights
-    origin: Vector,
+    pub origin: Vector,
     /// Index into node array
-    head_node: i32,
+    pub head_node: i32,
     /// Infex into face array
-    first_face: i32,
+    pub first_face: i32,
     /// Number of faces
-    num_faces: i32,
+    pub num_faces: i32,
 }
 
 impl BspParseable for Model {
+    const MIN_SIZE: usize = 48;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
-            mins: (data.read_f32(), data.read_f32(), data.read_f32()),
-            maxs: (data.read_f32(), data.read_f32(), data.read_f32()),
-            origin: (data.read_f32(), data.read_f32(), data.read_f32()),
+            mins: data.read_vec3(),
+            maxs: data.read_vec3(),
+            origin: data.read_vec3(),
             head_node: data.read_i32(),
             first_face: data.read_i32(),
             num_faces: data.read_i32(),
@@ -399,17 +833,26 @@ impl BspParseable for Model {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Brush {
     /// First brushside
-    first_side: i32,
+    pub first_side: i32,
     /// Number of brushsides
-    num_sides: i32,
+    pub num_sides: i32,
     /// Contents flags
-    contents: i32,
+    pub contents: i32,
+}
+
+impl Brush {
+    pub fn contents_flags(&self) -> ContentsFlags {
+        ContentsFlags(self.contents)
+    }
 }
 
 impl BspParseable for Brush {
+    const MIN_SIZE: usize = 12;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
             first_side: data.read_i32(),
@@ -419,19 +862,22 @@ impl BspParseable for Brush {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Brushside {
     /// Facing out of leaf
-    plane_num: u16,
+    pub plane_num: u16,
     /// Texture info
-    texinfo: i16,
+    pub texinfo: i16,
     /// Displacement info
-    dispinfo: i16,
+    pub dispinfo: i16,
     /// Is the side a bevel plane?
-    bevel: i16,
+    pub bevel: i16,
 }
 
 impl BspParseable for Brushside {
+    const MIN_SIZE: usize = 8;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
             plane_num: data.read_u16(),
@@ -442,13 +888,78 @@ impl BspParseable for Brushside {
     }
 }
 
-#[derive(Debug)]
+/// Number of face slots stored in each overlay
+pub const OVERLAY_BSP_FACE_COUNT: usize = 64;
+
+/// A decal-like texture projected onto a set of faces
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Overlay {
+    pub id: i32,
+    /// Texture info
+    pub texinfo: i16,
+    /// Face count in the lower 14 bits, render order in the upper 2
+    pub face_count_and_render_order: u16,
+    /// Indices into the face array, only the first face_count() are used
+    pub faces: Vec<i32>,
+    pub u: [f32; 2],
+    pub v: [f32; 2],
+    /// Corners of the overlay
+    pub uv_points: [Vector; 4],
+    pub origin: Vector,
+    pub basis_normal: Vector,
+}
+
+impl Overlay {
+    pub fn face_count(&self) -> usize {
+        (self.face_count_and_render_order & 0x3FFF) as usize
+    }
+
+    pub fn render_order(&self) -> u16 {
+        self.face_count_and_render_order >> 14
+    }
+
+    /// The indices of the faces the overlay is projected onto
+    pub fn used_faces(&self) -> &[i32] {
+        &self.faces[..self.face_count().min(self.faces.len())]
+    }
+}
+
+impl BspParseable for Overlay {
+    const MIN_SIZE: usize = 352;
+
+    fn from_reader(data: &mut LumpReader) -> Self {
+        Self {
+            id: data.read_i32(),
+            texinfo: data.read_i16(),
+            face_count_and_render_order: data.read_u16(),
+            faces: (0..OVERLAY_BSP_FACE_COUNT)
+                .map(|_| data.read_i32())
+                .collect(),
+            u: [data.read_f32(), data.read_f32()],
+            v: [data.read_f32(), data.read_f32()],
+            uv_points: [
+                data.read_vec3(),
+                data.read_vec3(),
+                data.read_vec3(),
+                data.read_vec3(),
+            ],
+            origin: data.read_vec3(),
+            basis_normal: data.read_vec3(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Area {
-    num_area_portals: i32,
-    first_area_portal: i32,
+    pub num_area_portals: i32,
+    pub first_area_portal: i32,
 }
 
 impl BspParseable for Area {
+    const MIN_SIZE: usize = 8;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
             num_area_portals: data.read_i32(),
@@ -457,16 +968,19 @@ impl BspParseable for Area {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AreaPortal {
-    portal_key: u16,
-    other_area: u16,
-    first_clip_portal_vert: u16,
-    num_clip_portal_verts: u16,
-    plane_num: i32,
+    pub portal_key: u16,
+    pub other_area: u16,
+    pub first_clip_portal_vert: u16,
+    pub num_clip_portal_verts: u16,
+    pub plane_num: i32,
 }
 
 impl BspParseable for AreaPortal {
+    const MIN_SIZE: usize = 12;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         Self {
             portal_key: data.read_u16(),
@@ -478,12 +992,139 @@ impl BspParseable for AreaPortal {
     }
 }
 
-#[derive(Debug)]
+/// A point the compiler renders an env_cubemap from
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cubemap {
+    pub origin: [i32; 3],
+    /// Resolution as a power of two, e.g. 6 for 32x32. Zero uses the default size.
+    pub size: i32,
+}
+
+impl BspParseable for Cubemap {
+    const MIN_SIZE: usize = 16;
+
+    fn from_reader(data: &mut LumpReader) -> Self {
+        Self {
+            origin: [data.read_i32(), data.read_i32(), data.read_i32()],
+            size: data.read_i32(),
+        }
+    }
+}
+
+impl Cubemap {
+    pub fn origin_vector(&self) -> Vector {
+        (
+            self.origin[0] as f32,
+            self.origin[1] as f32,
+            self.origin[2] as f32,
+        )
+    }
+
+    /// Width of each face of the cubemap in pixels, `1 << (size - 1)`,
+    /// or 32 when the size is left at zero like the engine does
+    pub fn resolution(&self) -> i32 {
+        if self.size <= 0 {
+            32
+        } else {
+            1 << (self.size - 1).min(30)
+        }
+    }
+}
+
+/// A light the compiler baked into the lightmaps, kept so models can be lit by the nearest lights
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WorldLight {
+    pub origin: Vector,
+    /// Color scaled by brightness, in linear light
+    pub intensity: Vector,
+    /// Direction of spotlights and surface lights
+    pub normal: Vector,
+    /// Offset from the origin shadows are cast from, only stored in version 1 lumps
+    pub shadow_cast_offset: Option<Vector>,
+    /// Visibility cluster the light is in
+    pub cluster: i32,
+    /// 0 surface, 1 point, 2 spotlight, 3 skylight, 4 quake light, 5 ambient skylight
+    pub emit_type: i32,
+    /// Light style for switchable and flickering lights, 0 if it's always on
+    pub style: i32,
+    /// Cosine of the spotlight's inner cone
+    pub stopdot: f32,
+    /// Cosine of the spotlight's outer cone
+    pub stopdot2: f32,
+    pub exponent: f32,
+    /// Distance past which the light is cut off, 0 for no limit
+    pub radius: f32,
+    pub constant_attn: f32,
+    pub linear_attn: f32,
+    pub quadratic_attn: f32,
+    pub flags: i32,
+    /// Texinfo of the face emitting a surface light
+    pub texinfo: i32,
+    /// Entity that created the light
+    pub owner: i32,
+}
+
+impl WorldLight {
+    /// Reads a light laid out for the given Worldlights lump version.
+    /// Version 1 adds the shadow cast offset after the normal.
+    pub fn from_reader_versioned(data: &mut LumpReader, version: i32) -> Self {
+        let start = data.get_pos();
+
+        let light = Self {
+            origin: data.read_vec3(),
+            intensity: data.read_vec3(),
+            normal: data.read_vec3(),
+            shadow_cast_offset: if version >= 1 {
+                Some(data.read_vec3())
+            } else {
+                None
+            },
+            cluster: data.read_i32(),
+            emit_type: data.read_i32(),
+            style: data.read_i32(),
+            stopdot: data.read_f32(),
+            stopdot2: data.read_f32(),
+            exponent: data.read_f32(),
+            radius: data.read_f32(),
+            constant_attn: data.read_f32(),
+            linear_attn: data.read_f32(),
+            quadratic_attn: data.read_f32(),
+            flags: data.read_i32(),
+            texinfo: data.read_i32(),
+            owner: data.read_i32(),
+        };
+
+        debug_assert_eq!(data.get_pos() - start, Self::size(version));
+
+        light
+    }
+
+    /// Size in bytes of a light in the given Worldlights lump version
+    pub fn size(version: i32) -> usize {
+        if version >= 1 {
+            100
+        } else {
+            88
+        }
+    }
+}
+
+impl BspParseable for WorldLight {
+    fn from_reader(data: &mut LumpReader) -> Self {
+        Self::from_reader_versioned(data, 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CDispSubNeighbor {
-    neighbor_index: u16,
-    neighbor_orientation: u8,
-    span: u8,
-    neighbor_span: u8,
+    /// Index of the neighboring displacement, 0xFFFF if there isn't one
+    pub neighbor_index: u16,
+    pub neighbor_orientation: u8,
+    pub span: u8,
+    pub neighbor_span: u8,
 }
 
 impl BspParseable for CDispSubNeighbor {
@@ -492,6 +1133,8 @@ impl BspParseable for CDispSubNeighbor {
         let neighbor_orientation = data.read_u8();
         let span = data.read_u8();
         let neighbor_span = data.read_u8();
+        data.skip_bytes(1); // Padding
+
         Self {
             neighbor_index,
             neighbor_orientation,
@@ -501,104 +1144,134 @@ impl BspParseable for CDispSubNeighbor {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CDispNeighbor {
-    sub_neighbors: Vec<CDispSubNeighbor>,
+    /// Each half of the edge can border a different displacement
+    pub sub_neighbors: [CDispSubNeighbor; 2],
 }
 
 impl BspParseable for CDispNeighbor {
     fn from_reader(data: &mut LumpReader) -> Self {
-        let mut out = Self {
-            sub_neighbors: vec![],
-        };
-        for i in 0..2 {
-            let neighbor = CDispSubNeighbor::from_reader(data);
-            let is_last = neighbor.neighbor_span == 0;
-            out.sub_neighbors.push(neighbor);
+        Self {
+            sub_neighbors: [(); 2].map(|_| CDispSubNeighbor::from_reader(data)),
         }
-        out
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CDispCornerNeighbors {
-    neighbors: [u16; 4],
-    num_neighbors: u8,
+    /// Indices of the displacements, the first num_neighbors of them are used
+    pub neighbors: [u16; 4],
+    pub num_neighbors: u8,
 }
 
 impl BspParseable for CDispCornerNeighbors {
     fn from_reader(data: &mut LumpReader) -> Self {
+        let neighbors = [(); 4].map(|_| data.read_u16());
+        let num_neighbors = data.read_u8();
+        data.skip_bytes(1); // Padding
+
         Self {
-            neighbors: [
-                data.read_u16(),
-                data.read_u16(),
-                data.read_u16(),
-                data.read_u16(),
-            ],
-            num_neighbors: data.read_u8(),
+            neighbors,
+            num_neighbors,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DisplacementInfo {
-    start_position: Vector,
-    disp_vert_start: i32,
-    disp_tri_start: i32,
-    power: i32,
-    min_tesselation: i32,
-    smoothing_angle: f32,
-    contents: i32,
-    map_face: u16,
-    lightmap_alpha_start: i32,
-    lightmap_sample_position_start: i32,
-    neighbor_data: (), // Temporary padding (90 bytes because I am lost)
-    allowed_verts: Vec<u32>,
+    pub start_position: Vector,
+    pub disp_vert_start: i32,
+    pub disp_tri_start: i32,
+    pub power: i32,
+    pub min_tesselation: i32,
+    pub smoothing_angle: f32,
+    pub contents: i32,
+    pub map_face: u16,
+    pub lightmap_alpha_start: i32,
+    pub lightmap_sample_position_start: i32,
+    /// The displacements along each edge, in the order left, top, right, bottom
+    pub edge_neighbors: [CDispNeighbor; 4],
+    /// The displacements touching each corner
+    pub corner_neighbors: [CDispCornerNeighbors; 4],
+    /// Bit per vertex of the tesselation, set for the vertices kept at every level of detail
+    pub allowed_verts: [u32; 10],
 }
 
 impl BspParseable for DisplacementInfo {
+    const MIN_SIZE: usize = 176;
+
     fn from_reader(data: &mut LumpReader) -> Self {
+        let start_position = data.read_vec3();
+        let disp_vert_start = data.read_i32();
+        let disp_tri_start = data.read_i32();
+        let power = data.read_i32();
+        let min_tesselation = data.read_i32();
+        let smoothing_angle = data.read_f32();
+        let contents = data.read_i32();
+        let map_face = data.read_u16();
+        data.skip_bytes(2); // Padding
+
         Self {
-            start_position: Vector::from_reader(data),
-            disp_vert_start: data.read_i32(),
-            disp_tri_start: data.read_i32(),
-            power: data.read_i32(),
-            min_tesselation: data.read_i32(),
-            smoothing_angle: data.read_f32(),
-            contents: data.read_i32(),
-            map_face: data.read_u16(),
+            start_position,
+            disp_vert_start,
+            disp_tri_start,
+            power,
+            min_tesselation,
+            smoothing_angle,
+            contents,
+            map_face,
             lightmap_alpha_start: data.read_i32(),
             lightmap_sample_position_start: data.read_i32(),
-            neighbor_data: data.skip_bytes(90), // Skips the neighbor section because my readers are broken.
-            allowed_verts: data.read_x_u32(10),
+            edge_neighbors: [(); 4].map(|_| CDispNeighbor::from_reader(data)),
+            corner_neighbors: [(); 4].map(|_| CDispCornerNeighbors::from_reader(data)),
+            allowed_verts: [(); 10].map(|_| data.read_u32()),
         }
     }
 }
 
-/* This section is broken, I'm not sure why
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DispVert {
+    /// Direction of the offset from the base surface
+    pub vec: Vector,
+    /// Distance of the offset along vec
+    pub dist: f32,
+    /// Alpha for blending between the two textures of a blend material
+    pub alpha: f32,
+}
 
-pub type PhysicsDisplacement = u16;
+impl BspParseable for DispVert {
+    const MIN_SIZE: usize = 20;
 
-impl BspParseable for PhysicsDisplacement {
     fn from_reader(data: &mut LumpReader) -> Self {
-    //println!("{} : {}", data.get_pos(), data.get_len());
-    data.read_u16()
+        Self {
+            vec: data.read_vec3(),
+            dist: data.read_f32(),
+            alpha: data.read_f32(),
+        }
     }
 }
- */
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CollisionData {
-    size: i32,
-    collision_data: Vec<u8>,
+    pub size: i32,
+    pub collision_data: Vec<u8>,
 }
 
 impl BspParseable for CollisionData {
+    const MIN_SIZE: usize = 4;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         let size = data.read_i32();
-        let collision_data =
-            data.get_data()[data.get_pos()..data.get_pos() + size as usize].to_vec();
-        data.skip_bytes(size as usize);
+        // Cut short rather than read past the lump if the size is corrupt
+        let len = (size.max(0) as usize).min(data.remaining());
+        let collision_data = data.get_data()[data.get_pos()..data.get_pos() + len].to_vec();
+        data.skip_bytes(len);
 
         Self {
             size,
@@ -607,17 +1280,20 @@ impl BspParseable for CollisionData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PhysicsModel {
-    model_index: i32,
-    data_size: i32,
-    keydata_size: i32,
-    solid_count: i32,
-    collision_data: Vec<CollisionData>,
-    text_data: String,
+    pub model_index: i32,
+    pub data_size: i32,
+    pub keydata_size: i32,
+    pub solid_count: i32,
+    pub collision_data: Vec<CollisionData>,
+    pub text_data: String,
 }
 
 impl BspParseable for PhysicsModel {
+    const MIN_SIZE: usize = 16;
+
     fn from_reader(data: &mut LumpReader) -> Self {
         let mut out = PhysicsModel {
             model_index: data.read_i32(),
@@ -629,14 +1305,164 @@ impl BspParseable for PhysicsModel {
         };
 
         for _ in 0..out.solid_count {
+            if data.remaining() < CollisionData::MIN_SIZE {
+                break;
+            }
             out.collision_data.push(CollisionData::from_reader(data))
         }
 
-        out.text_data = String::from_utf8_lossy(
-            &data.get_data()[data.get_pos()..data.get_pos() + out.keydata_size as usize],
-        )
-        .to_string();
-        data.skip_bytes(out.keydata_size as usize);
+        let len = (out.keydata_size.max(0) as usize).min(data.remaining());
+        out.text_data =
+            String::from_utf8_lossy(&data.get_data()[data.get_pos()..data.get_pos() + len])
+                .to_string();
+        data.skip_bytes(len);
         out
     }
 }
+
+#[test]
+fn test_leaf_versions() {
+    for version in [0, 1] {
+        let mut lump = vec![];
+        for cluster in [3i16, 4] {
+            lump.extend_from_slice(&1i32.to_le_bytes());
+            lump.extend_from_slice(&cluster.to_le_bytes());
+            lump.extend_from_slice(&[0; 24]);
+            if version == 0 {
+                lump.extend_from_slice(&[cluster as u8; 24]);
+            }
+            lump.extend_from_slice(&[0; 2]);
+        }
+        assert_eq!(lump.len(), 2 * Leaf::size(version));
+
+        let mut data = LumpReader::new(&lump);
+        let first = Leaf::from_reader_versioned(&mut data, version);
+        let second = Leaf::from_reader_versioned(&mut data, version);
+
+        assert!(data.at_end());
+        assert_eq!(first.cluster_opt(), Some(3));
+        assert_eq!(second.cluster, 4);
+        assert_eq!(second.contents, 1);
+        match (version, &second.ambient_lighting) {
+            (0, Some(cube)) => assert_eq!(cube[5].r, 4),
+            (1, None) => (),
+            _ => panic!("version {} read the wrong ambient lighting", version),
+        }
+    }
+}
+
+#[test]
+fn test_plane_distance() {
+    let plane = Plane {
+        normal: (0.0, 0.0, 1.0),
+        dist_from_origin: 16.0,
+        r#type: 2,
+    };
+
+    assert_eq!(plane.distance_to((5.0, -3.0, 48.0)), 32.0);
+    assert_eq!(plane.distance_to((0.0, 0.0, 16.0)), 0.0);
+    assert_eq!(plane.distance_to((0.0, 0.0, 0.0)), -16.0);
+}
+
+#[test]
+fn test_displacement_info_layout() {
+    let mut entry = vec![];
+    for value in [1.0f32, 2.0, 3.0] {
+        entry.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [0i32, 0, 3, 0] {
+        entry.extend_from_slice(&value.to_le_bytes());
+    }
+    entry.extend_from_slice(&0f32.to_le_bytes());
+    entry.extend_from_slice(&1i32.to_le_bytes());
+    entry.extend_from_slice(&[7, 0, 0, 0]); // map_face and padding
+    entry.extend_from_slice(&100i32.to_le_bytes());
+    entry.extend_from_slice(&200i32.to_le_bytes());
+    // Edge neighbors, with the left edge's first half against displacement 5
+    entry.extend_from_slice(&[5, 0, 2, 1, 1, 0]);
+    entry.extend_from_slice(&[0xff; 6]);
+    entry.extend_from_slice(&[0xff; 36]);
+    // Corner neighbors, with the first corner touching displacements 8 and 9
+    entry.extend_from_slice(&[8, 0, 9, 0, 0, 0, 0, 0, 2, 0]);
+    entry.extend_from_slice(&[0; 30]);
+    for i in 0..10u32 {
+        entry.extend_from_slice(&i.to_le_bytes());
+    }
+    assert_eq!(entry.len(), 176);
+
+    let mut data = LumpReader::new(&entry);
+    let info = DisplacementInfo::from_reader(&mut data);
+
+    assert!(data.at_end());
+    assert_eq!(info.power, 3);
+    assert_eq!(info.map_face, 7);
+    assert_eq!(info.lightmap_alpha_start, 100);
+    assert_eq!(info.lightmap_sample_position_start, 200);
+    assert_eq!(info.edge_neighbors[0].sub_neighbors[0].neighbor_index, 5);
+    assert_eq!(
+        info.edge_neighbors[0].sub_neighbors[0].neighbor_orientation,
+        2
+    );
+    assert_eq!(
+        info.edge_neighbors[0].sub_neighbors[1].neighbor_index,
+        0xffff
+    );
+    assert_eq!(info.corner_neighbors[0].neighbors[..2], [8, 9]);
+    assert_eq!(info.corner_neighbors[0].num_neighbors, 2);
+    assert_eq!(info.allowed_verts[9], 9);
+}
+
+#[test]
+fn test_leaf_area_flags() {
+    let mut data = LumpReader::new(&[0; 32]);
+    let mut leaf = Leaf::from_reader(&mut data);
+
+    // Area 300 with the sky and 2D sky flags
+    leaf.area_flags = ((0x05 << 9) | 300u16) as i16;
+    assert_eq!(leaf.area(), 300);
+    assert_eq!(leaf.flags(), Leaf::FLAGS_SKY | Leaf::FLAGS_SKY2D);
+
+    // Every flag set makes the packed i16 negative
+    leaf.area_flags = (0x7F << 9) as u16 as i16;
+    assert!(leaf.area_flags < 0);
+    assert_eq!(leaf.area(), 0);
+    assert_eq!(leaf.flags(), 0x7F);
+
+    leaf.cluster = -1;
+    assert_eq!(leaf.cluster_opt(), None);
+}
+
+#[test]
+fn test_world_light_versions() {
+    for version in [0, 1] {
+        let mut lump = vec![];
+        for float in [1.0f32, 2.0, 3.0, 50.0, 40.0, 30.0, 0.0, 0.0, -1.0] {
+            lump.extend_from_slice(&float.to_le_bytes());
+        }
+        if version == 1 {
+            lump.extend_from_slice(&[0; 12]);
+        }
+        for int in [7i32, 2, 0] {
+            lump.extend_from_slice(&int.to_le_bytes());
+        }
+        for float in [0.9f32, 0.7, 1.0, 256.0, 0.0, 0.0, 1.0] {
+            lump.extend_from_slice(&float.to_le_bytes());
+        }
+        for int in [0i32, -1, 12] {
+            lump.extend_from_slice(&int.to_le_bytes());
+        }
+        assert_eq!(lump.len(), WorldLight::size(version));
+
+        let mut data = LumpReader::new(&lump);
+        let light = WorldLight::from_reader_versioned(&mut data, version);
+
+        assert!(data.at_end());
+        assert_eq!(light.origin, (1.0, 2.0, 3.0));
+        assert_eq!(light.normal, (0.0, 0.0, -1.0));
+        assert_eq!(light.shadow_cast_offset.is_some(), version == 1);
+        assert_eq!(light.cluster, 7);
+        assert_eq!(light.emit_type, 2);
+        assert_eq!(light.radius, 256.0);
+        assert_eq!(light.owner, 12);
+    }
+}
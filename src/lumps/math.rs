@@ -0,0 +1,46 @@
+//! Small helpers for doing math on the `Vector` tuples used by the lump types
+
+use crate::lumps::lump_types::Vector;
+
+pub fn add(a: Vector, b: Vector) -> Vector {
+    (a.0 + b.0, a.1 + b.1, a.2 + b.2)
+}
+
+pub fn sub(a: Vector, b: Vector) -> Vector {
+    (a.0 - b.0, a.1 - b.1, a.2 - b.2)
+}
+
+pub fn scale(a: Vector, s: f32) -> Vector {
+    (a.0 * s, a.1 * s, a.2 * s)
+}
+
+pub fn dot(a: Vector, b: Vector) -> f32 {
+    a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+}
+
+pub fn cross(a: Vector, b: Vector) -> Vector {
+    (
+        a.1 * b.2 - a.2 * b.1,
+        a.2 * b.0 - a.0 * b.2,
+        a.0 * b.1 - a.1 * b.0,
+    )
+}
+
+pub fn length(a: Vector) -> f32 {
+    dot(a, a).sqrt()
+}
+
+/// Returns the vector scaled to a length of 1, or the zero vector if it has no length
+pub fn normalize(a: Vector) -> Vector {
+    let len = length(a);
+    if len == 0.0 {
+        (0.0, 0.0, 0.0)
+    } else {
+        scale(a, 1.0 / len)
+    }
+}
+
+/// Linearly interpolates between a and b, t = 0 being a and t = 1 being b
+pub fn lerp(a: Vector, b: Vector, t: f32) -> Vector {
+    add(a, scale(sub(b, a), t))
+}
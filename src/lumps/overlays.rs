@@ -0,0 +1,57 @@
+use crate::lumps::lump_types::*;
+use crate::lumps::LumpParser::ParsedLumps;
+
+use std::collections::HashMap;
+
+/// Maps faces to the overlays projected onto them, built once so a renderer can look up
+/// every face without scanning all of the overlays each time
+#[derive(Debug)]
+pub struct OverlayIndex<'a> {
+    overlays: &'a [Overlay],
+    by_face: HashMap<usize, Vec<usize>>,
+}
+
+impl<'a> OverlayIndex<'a> {
+    /// Returns the overlays projected onto a face, in lump order
+    pub fn overlays_on_face(&self, face_index: usize) -> Vec<&'a Overlay> {
+        self.by_face
+            .get(&face_index)
+            .map_or_else(Vec::new, |overlays| {
+                overlays.iter().map(|&i| &self.overlays[i]).collect()
+            })
+    }
+}
+
+impl ParsedLumps {
+    /// Builds an index from faces to the overlays on them
+    pub fn overlay_index(&self) -> OverlayIndex<'_> {
+        let mut by_face: HashMap<usize, Vec<usize>> = HashMap::new();
+
+        for (i, overlay) in self.overlays.iter().enumerate() {
+            for &face in overlay.used_faces() {
+                if face >= 0 {
+                    by_face.entry(face as usize).or_default().push(i);
+                }
+            }
+        }
+
+        OverlayIndex {
+            overlays: &self.overlays,
+            by_face,
+        }
+    }
+
+    /// Returns the overlays projected onto a face.
+    /// When looking up many faces, build an overlay_index once and query that instead.
+    pub fn overlays_on_face(&self, face_index: usize) -> Vec<&Overlay> {
+        self.overlays
+            .iter()
+            .filter(|overlay| {
+                overlay
+                    .used_faces()
+                    .iter()
+                    .any(|&face| face >= 0 && face as usize == face_index)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,199 @@
+use crate::lumps::LumpParser::ParsedLumps;
+
+#[cfg(feature = "zip")]
+use std::fs;
+#[cfg(feature = "zip")]
+use std::io::{Cursor, Read};
+#[cfg(feature = "zip")]
+use std::path::{Component, Path, PathBuf};
+
+/// The pakfile lump, a ZIP archive of content embedded in the map such as custom materials
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pakfile {
+    /// The ZIP archive's bytes
+    pub data: Vec<u8>,
+}
+
+/// Errors that can occur while reading the pakfile
+#[cfg(feature = "zip")]
+#[derive(Debug, thiserror::Error)]
+pub enum PakError {
+    /// The map has no pakfile lump
+    #[error("the map has no pakfile")]
+    Missing,
+    /// The pakfile isn't a valid ZIP archive
+    #[error("invalid pakfile: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    /// A file couldn't be written
+    #[error("failed to write pakfile entry: {0}")]
+    Io(#[from] std::io::Error),
+    /// An entry's path is absolute or leaves the directory it's extracted to
+    #[error("pakfile entry {0:?} has an unsafe path")]
+    UnsafePath(String),
+}
+
+#[cfg(feature = "zip")]
+impl Pakfile {
+    pub(crate) fn archive(&self) -> Result<zip::ZipArchive<Cursor<&[u8]>>, PakError> {
+        Ok(zip::ZipArchive::new(Cursor::new(&self.data[..]))?)
+    }
+
+    /// Names of every entry in the archive, directories included, in the order they're stored.
+    /// Empty if the pakfile isn't a valid ZIP archive.
+    pub fn entries(&self) -> Vec<String> {
+        let mut archive = match self.archive() {
+            Ok(archive) => archive,
+            Err(_) => return vec![],
+        };
+
+        (0..archive.len())
+            .filter_map(|i| Some(archive.by_index_raw(i).ok()?.name().to_string()))
+            .collect()
+    }
+
+    /// Reads the decompressed contents of an entry.
+    /// The engine's filesystem isn't case sensitive, so neither is the lookup, and
+    /// `\` separators match `/`. If several entries match, the first one stored is read.
+    /// None if there's no such file, it can't be read, or it decompresses to more than
+    /// DEFAULT_MAX_DECOMPRESSED_SIZE.
+    pub fn read_file(&self, name: &str) -> Option<Vec<u8>> {
+        let normalize = |name: &str| name.replace('\\', "/").to_lowercase();
+        let wanted = normalize(name);
+
+        let mut archive = self.archive().ok()?;
+        let index = (0..archive.len()).find(|&i| {
+            archive
+                .by_index_raw(i)
+                .is_ok_and(|entry| normalize(entry.name()) == wanted)
+        })?;
+
+        let entry = archive.by_index(index).ok()?;
+        if entry.is_dir() {
+            return None;
+        }
+
+        // The size in the ZIP header comes from the map, so it isn't used to allocate
+        let limit = crate::lumps::LumpParser::DEFAULT_MAX_DECOMPRESSED_SIZE;
+        let mut data = vec![];
+        entry.take(limit as u64 + 1).read_to_end(&mut data).ok()?;
+        if data.len() > limit {
+            return None;
+        }
+        Some(data)
+    }
+
+    /// Writes every entry into a directory, keeping the archive's directory structure,
+    /// and returns the paths of the files written.
+    /// Entries with paths that would escape the directory are rejected before anything is written.
+    pub fn extract(&self, dest_dir: &Path) -> Result<Vec<PathBuf>, PakError> {
+        let mut archive = self.archive()?;
+
+        let mut paths = Vec::with_capacity(archive.len());
+        for i in 0..archive.len() {
+            let name = archive.by_index_raw(i)?.name().to_string();
+            paths.push(safe_path(&name).ok_or(PakError::UnsafePath(name))?);
+        }
+
+        let mut written = vec![];
+        for (i, path) in paths.into_iter().enumerate() {
+            let mut entry = archive.by_index(i)?;
+            let dest = dest_dir.join(path);
+
+            if entry.is_dir() {
+                fs::create_dir_all(&dest)?;
+                continue;
+            }
+
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            std::io::copy(&mut entry, &mut fs::File::create(&dest)?)?;
+            written.push(dest);
+        }
+
+        Ok(written)
+    }
+}
+
+/// Turns an entry name into a relative path, None if it's absolute or goes up a directory.
+/// Pakfiles are built by community tools, so they can't be trusted to be well formed.
+#[cfg(feature = "zip")]
+fn safe_path(name: &str) -> Option<PathBuf> {
+    // Some tools write Windows separators
+    let path = PathBuf::from(name.replace('\\', "/"));
+
+    if path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+impl ParsedLumps {
+    /// Unpacks the pakfile into a directory, see Pakfile::extract
+    #[cfg(feature = "zip")]
+    pub fn extract_pakfile(&self, dest_dir: &Path) -> Result<Vec<PathBuf>, PakError> {
+        self.pakfile
+            .as_ref()
+            .ok_or(PakError::Missing)?
+            .extract(dest_dir)
+    }
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn test_pakfile_safe_paths() {
+    assert_eq!(
+        safe_path("materials\\custom/wall.vmt"),
+        Some(PathBuf::from("materials/custom/wall.vmt"))
+    );
+    assert_eq!(safe_path("../outside.txt"), None);
+    assert_eq!(safe_path("materials/../../outside.txt"), None);
+    assert_eq!(safe_path("/etc/passwd"), None);
+}
+
+#[cfg(feature = "zip")]
+#[test]
+fn test_pakfile_read_file() {
+    use std::io::Write;
+
+    let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    zip.start_file("materials/custom/Wall.vmt", options)
+        .unwrap();
+    zip.write_all(b"LightmappedGeneric {}").unwrap();
+    // Only differs by case, the first entry wins
+    zip.start_file("materials/custom/WALL.vmt", options)
+        .unwrap();
+    zip.write_all(b"UnlitGeneric {}").unwrap();
+    zip.start_file("sound/ambient.wav", options).unwrap();
+    zip.write_all(b"RIFF").unwrap();
+    let pakfile = Pakfile {
+        data: zip.finish().unwrap().into_inner(),
+    };
+
+    assert_eq!(
+        pakfile.entries(),
+        [
+            "materials/custom/Wall.vmt",
+            "materials/custom/WALL.vmt",
+            "sound/ambient.wav"
+        ]
+    );
+    assert_eq!(
+        pakfile.read_file("MATERIALS\\custom\\wall.vmt").as_deref(),
+        Some(&b"LightmappedGeneric {}"[..])
+    );
+    assert_eq!(pakfile.read_file("missing.vmt"), None);
+
+    let corrupt = Pakfile {
+        data: vec![1, 2, 3],
+    };
+    assert!(corrupt.entries().is_empty());
+    assert_eq!(corrupt.read_file("sound/ambient.wav"), None);
+}
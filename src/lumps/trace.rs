@@ -0,0 +1,457 @@
+use crate::error::BspError;
+use crate::lumps::clamped_slice;
+use crate::lumps::lump_types::*;
+use crate::lumps::math::*;
+use crate::lumps::LumpParser::ParsedLumps;
+
+/// How deep find_leaf and raycast walk the BSP tree before giving up. Compiled maps are
+/// far shallower, the engine's own limit on nodes is 65536.
+pub const DEFAULT_MAX_TRAVERSAL_DEPTH: usize = 4096;
+
+/// Where a ray first entered solid space
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    /// How far along the ray the hit is, 0 being the start and 1 the end
+    pub fraction: f32,
+    pub point: Vector,
+    /// Normal of the plane that was hit, facing back towards the start of the ray.
+    /// Zero if the ray started inside solid space.
+    pub plane_normal: Vector,
+}
+
+/// True unless the box is entirely behind one of the planes
+fn box_in_frustum((mins, maxs): ([f32; 3], [f32; 3]), frustum: &[Plane; 6]) -> bool {
+    frustum.iter().all(|plane| {
+        // The corner furthest along the normal is the last to leave the plane's front side
+        let pick = |axis: usize, n: f32| if n >= 0.0 { maxs[axis] } else { mins[axis] };
+        let corner = (
+            pick(0, plane.normal.0),
+            pick(1, plane.normal.1),
+            pick(2, plane.normal.2),
+        );
+
+        plane.distance_to(corner) >= 0.0
+    })
+}
+
+/// Guards a trace against trees that are too deep or loop
+struct TraceWalk {
+    max_depth: usize,
+    /// Nodes between the root and the node being traced through
+    on_path: Vec<bool>,
+}
+
+/// One piece of the ray, clipped to the space of the node being traced through
+struct Segment {
+    start_fraction: f32,
+    end_fraction: f32,
+    start: Vector,
+    end: Vector,
+}
+
+impl ParsedLumps {
+    /// The node the world's tree starts at
+    fn world_root(&self) -> NodeChild {
+        let head_node = self.models.first().map_or(0, |model| model.head_node);
+        NodeChild::Node(head_node.max(0) as u32)
+    }
+
+    /// Walks the world's BSP tree down to the leaf containing a point.
+    /// None if the tree is corrupt, see try_find_leaf to tell why.
+    pub fn find_leaf(&self, point: Vector) -> Option<usize> {
+        self.try_find_leaf(point, DEFAULT_MAX_TRAVERSAL_DEPTH)
+            .ok()
+            .flatten()
+    }
+
+    /// Like find_leaf, but fails if the tree is deeper than max_depth or loops back on
+    /// itself, as a hostile map can set up. Ok(None) if a node or leaf is missing.
+    pub fn try_find_leaf(
+        &self,
+        point: Vector,
+        max_depth: usize,
+    ) -> Result<Option<usize>, BspError> {
+        let mut child = self.world_root();
+        let mut visited = vec![false; self.nodes.len()];
+
+        for _ in 0..=max_depth {
+            match child {
+                NodeChild::Leaf(leaf) => {
+                    return Ok(Some(leaf as usize).filter(|&leaf| leaf < self.leaves.len()))
+                }
+                NodeChild::Node(index) => {
+                    let node = match self.nodes.get(index as usize) {
+                        Some(node) => node,
+                        None => return Ok(None),
+                    };
+                    if std::mem::replace(&mut visited[index as usize], true) {
+                        return Err(BspError::NodeCycle { node: index });
+                    }
+                    let plane = match self.planes.get(node.plane_num as usize) {
+                        Some(plane) => plane,
+                        None => return Ok(None),
+                    };
+
+                    let side = if plane.distance_to(point) >= 0.0 {
+                        0
+                    } else {
+                        1
+                    };
+                    child = node.child(side);
+                }
+            }
+        }
+
+        Err(BspError::TraversalTooDeep { limit: max_depth })
+    }
+
+    /// Traces a line through the world's BSP tree, returning where it first enters a leaf
+    /// with solid contents, or None if it reaches the end unobstructed.
+    ///
+    /// This is a point trace like SV_RecursiveHullCheck, so it only tests the leaves of the
+    /// tree and ignores brush entities, displacements and static props.
+    pub fn raycast(&self, start: Vector, end: Vector) -> Option<RayHit> {
+        self.try_raycast(start, end, DEFAULT_MAX_TRAVERSAL_DEPTH)
+            .ok()
+            .flatten()
+    }
+
+    /// Like raycast, but fails if the tree is deeper than max_depth or loops back on itself
+    pub fn try_raycast(
+        &self,
+        start: Vector,
+        end: Vector,
+        max_depth: usize,
+    ) -> Result<Option<RayHit>, BspError> {
+        let segment = Segment {
+            start_fraction: 0.0,
+            end_fraction: 1.0,
+            start,
+            end,
+        };
+        let mut walk = TraceWalk {
+            max_depth,
+            on_path: vec![false; self.nodes.len()],
+        };
+
+        self.trace(self.world_root(), segment, (0.0, 0.0, 0.0), 0, &mut walk)
+    }
+
+    /// Walks the world's BSP tree, skipping any node whose bounding box is outside of the
+    /// frustum, and returns the indices of the leaves that are at least partly inside it.
+    /// The frustum planes' normals point into the frustum.
+    pub fn visible_nodes(&self, frustum: &[Plane; 6]) -> Vec<usize> {
+        let mut out = vec![];
+        let mut stack = vec![self.world_root()];
+        let mut visited = 0;
+
+        while let Some(child) = stack.pop() {
+            // Stop on trees with loops, a valid one visits each node and leaf once
+            visited += 1;
+            if visited > self.nodes.len() + self.leaves.len() {
+                break;
+            }
+
+            match child {
+                NodeChild::Leaf(index) => {
+                    if let Some(leaf) = self.leaves.get(index as usize) {
+                        if box_in_frustum(leaf.bounds(), frustum) {
+                            out.push(index as usize);
+                        }
+                    }
+                }
+                NodeChild::Node(index) => {
+                    if let Some(node) = self.nodes.get(index as usize) {
+                        if box_in_frustum(node.bounds(), frustum) {
+                            stack.push(node.child(1));
+                            stack.push(node.child(0));
+                        }
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Returns the indices of the brushes a brush model was built from, found through the
+    /// leaves under its head node. The world, model 0, returns every world brush.
+    pub fn model_brushes(&self, model_index: usize) -> Vec<usize> {
+        let model = match self.models.get(model_index) {
+            Some(model) if model.head_node >= 0 => model,
+            _ => return vec![],
+        };
+
+        let mut brushes = vec![];
+        let mut stack = vec![NodeChild::Node(model.head_node as u32)];
+        let mut visited = 0;
+
+        while let Some(child) = stack.pop() {
+            // Stop on trees with loops, a valid one visits each node and leaf once
+            visited += 1;
+            if visited > self.nodes.len() + self.leaves.len() {
+                break;
+            }
+
+            match child {
+                NodeChild::Leaf(index) => {
+                    if let Some(leaf) = self.leaves.get(index as usize) {
+                        let leaf_brushes = clamped_slice(
+                            &self.leaf_brushes,
+                            leaf.first_leaf_brush as i64,
+                            leaf.num_leaf_brushes as i64,
+                        );
+                        brushes.extend(leaf_brushes.iter().map(|&brush| brush as usize));
+                    }
+                }
+                NodeChild::Node(index) => {
+                    if let Some(node) = self.nodes.get(index as usize) {
+                        stack.push(node.child(1));
+                        stack.push(node.child(0));
+                    }
+                }
+            }
+        }
+
+        // Brushes spanning several leaves are listed in each of them
+        brushes.sort_unstable();
+        brushes.dedup();
+        brushes
+    }
+
+    /// Traces a segment through a subtree. plane_normal is the normal of the last plane
+    /// the segment crossed, which is what it hits if this subtree is solid.
+    fn trace(
+        &self,
+        child: NodeChild,
+        segment: Segment,
+        plane_normal: Vector,
+        depth: usize,
+        walk: &mut TraceWalk,
+    ) -> Result<Option<RayHit>, BspError> {
+        if depth > walk.max_depth {
+            return Err(BspError::TraversalTooDeep {
+                limit: walk.max_depth,
+            });
+        }
+
+        let index = match child {
+            NodeChild::Leaf(leaf) => {
+                let leaf = match self.leaves.get(leaf as usize) {
+                    Some(leaf) => leaf,
+                    None => return Ok(None),
+                };
+
+                return Ok(if leaf.contents_flags().contains(ContentsFlags::SOLID) {
+                    Some(RayHit {
+                        fraction: segment.start_fraction,
+                        point: segment.start,
+                        plane_normal,
+                    })
+                } else {
+                    None
+                });
+            }
+            NodeChild::Node(index) => index,
+        };
+
+        if walk.on_path.get(index as usize) == Some(&true) {
+            return Err(BspError::NodeCycle { node: index });
+        }
+        if let Some(on_path) = walk.on_path.get_mut(index as usize) {
+            *on_path = true;
+        }
+        let hit = self.trace_node(index as usize, segment, plane_normal, depth, walk);
+        if let Some(on_path) = walk.on_path.get_mut(index as usize) {
+            *on_path = false;
+        }
+
+        hit
+    }
+
+    /// Splits a segment by a node's plane and traces the pieces through its children
+    fn trace_node(
+        &self,
+        index: usize,
+        segment: Segment,
+        plane_normal: Vector,
+        depth: usize,
+        walk: &mut TraceWalk,
+    ) -> Result<Option<RayHit>, BspError> {
+        let node = match self.nodes.get(index) {
+            Some(node) => node,
+            None => return Ok(None),
+        };
+        let plane = match self.planes.get(node.plane_num as usize) {
+            Some(plane) => plane,
+            None => return Ok(None),
+        };
+
+        let start_dist = plane.distance_to(segment.start);
+        let end_dist = plane.distance_to(segment.end);
+
+        if start_dist >= 0.0 && end_dist >= 0.0 {
+            return self.trace(node.child(0), segment, plane_normal, depth + 1, walk);
+        }
+        if start_dist < 0.0 && end_dist < 0.0 {
+            return self.trace(node.child(1), segment, plane_normal, depth + 1, walk);
+        }
+
+        // The segment crosses the plane, trace the near side first then the far side from
+        // where it crosses
+        let near = if start_dist < 0.0 { 1 } else { 0 };
+        let t = start_dist / (start_dist - end_dist);
+        let mid_fraction =
+            segment.start_fraction + (segment.end_fraction - segment.start_fraction) * t;
+        let mid = lerp(segment.start, segment.end, t);
+
+        let near_segment = Segment {
+            start_fraction: segment.start_fraction,
+            end_fraction: mid_fraction,
+            start: segment.start,
+            end: mid,
+        };
+        if let Some(hit) = self.trace(
+            node.child(near),
+            near_segment,
+            plane_normal,
+            depth + 1,
+            walk,
+        )? {
+            return Ok(Some(hit));
+        }
+
+        let far_segment = Segment {
+            start_fraction: mid_fraction,
+            end_fraction: segment.end_fraction,
+            start: mid,
+            end: segment.end,
+        };
+        // Facing the side the ray came from
+        let crossed_normal = if near == 0 {
+            plane.normal
+        } else {
+            scale(plane.normal, -1.0)
+        };
+        self.trace(
+            node.child(1 - near),
+            far_segment,
+            crossed_normal,
+            depth + 1,
+            walk,
+        )
+    }
+}
+
+#[test]
+fn test_raycast_single_plane() {
+    let mut parsed = ParsedLumps::default();
+    // x >= 0 is empty, x < 0 is solid
+    parsed.planes.push(Plane {
+        normal: (1.0, 0.0, 0.0),
+        dist_from_origin: 0.0,
+        r#type: 0,
+    });
+    parsed.nodes.push(crate::fixture::node([-1, -2]));
+    parsed
+        .leaves
+        .push(crate::fixture::leaf(ContentsFlags::EMPTY));
+    parsed
+        .leaves
+        .push(crate::fixture::leaf(ContentsFlags::SOLID));
+
+    assert_eq!(parsed.find_leaf((5.0, 0.0, 0.0)), Some(0));
+    assert_eq!(parsed.find_leaf((-5.0, 0.0, 0.0)), Some(1));
+
+    let hit = parsed.raycast((10.0, 0.0, 0.0), (-10.0, 0.0, 0.0)).unwrap();
+    assert_eq!(hit.fraction, 0.5);
+    assert_eq!(hit.point, (0.0, 0.0, 0.0));
+    assert_eq!(hit.plane_normal, (1.0, 0.0, 0.0));
+
+    assert_eq!(parsed.raycast((10.0, 0.0, 0.0), (1.0, 5.0, 0.0)), None);
+}
+
+#[test]
+fn test_model_brushes() {
+    use crate::fixture::node;
+    let leaf = |first_leaf_brush, num_leaf_brushes| Leaf {
+        first_leaf_brush,
+        num_leaf_brushes,
+        ..crate::fixture::leaf(ContentsFlags::SOLID)
+    };
+    let model = |head_node| Model {
+        mins: (0.0, 0.0, 0.0),
+        maxs: (0.0, 0.0, 0.0),
+        origin: (0.0, 0.0, 0.0),
+        head_node,
+        first_face: 0,
+        num_faces: 0,
+    };
+    let parsed = ParsedLumps {
+        entities: crate::lumps::entities::parse_entities(concat!(
+            "{\n\"classname\" \"worldspawn\"\n}\n",
+            "{\n\"classname\" \"prop_static\"\n\"model\" \"models/crate.mdl\"\n}\n",
+            "{\n\"classname\" \"func_brush\"\n\"model\" \"*1\"\n}\n",
+        )),
+        // The world is node 0 over leaf 0, the func_brush is node 1 over leaves 1 and 2
+        nodes: vec![node([-1, -1]), node([-2, -3])],
+        leaves: vec![leaf(0, 1), leaf(1, 2), leaf(2, 1)],
+        leaf_brushes: vec![0, 1, 2],
+        models: vec![model(0), model(1)],
+        ..Default::default()
+    };
+
+    let entity_models = parsed.entity_models();
+    assert_eq!(entity_models.len(), 1);
+    assert_eq!(entity_models[0].0, 1);
+    assert_eq!(entity_models[0].1["classname"], "func_brush");
+
+    // Brush 2 is in both leaves but only listed once
+    assert_eq!(parsed.model_brushes(1), [1, 2]);
+    assert_eq!(parsed.model_brushes(0), [0]);
+    assert!(parsed.model_brushes(2).is_empty());
+}
+
+#[test]
+fn test_traversal_limits() {
+    use crate::fixture::node;
+    let mut parsed = ParsedLumps {
+        planes: vec![Plane {
+            normal: (1.0, 0.0, 0.0),
+            dist_from_origin: 0.0,
+            r#type: 0,
+        }],
+        // Node 1 points back at node 0 on both sides
+        nodes: vec![node([1, 1]), node([0, 0])],
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        parsed.try_find_leaf((1.0, 0.0, 0.0), DEFAULT_MAX_TRAVERSAL_DEPTH),
+        Err(BspError::NodeCycle { node: 0 })
+    ));
+    assert!(matches!(
+        parsed.try_raycast(
+            (1.0, 0.0, 0.0),
+            (-1.0, 0.0, 0.0),
+            DEFAULT_MAX_TRAVERSAL_DEPTH
+        ),
+        Err(BspError::NodeCycle { node: 0 })
+    ));
+    assert_eq!(parsed.find_leaf((1.0, 0.0, 0.0)), None);
+
+    // A chain of 10 nodes is fine until the limit is lower than its depth
+    parsed.nodes = (0..10).map(|i| node([i + 1, i + 1])).collect();
+    parsed.nodes[9] = node([-1, -1]);
+    parsed.leaves = vec![crate::fixture::leaf(ContentsFlags::EMPTY)];
+
+    assert_eq!(parsed.try_find_leaf((1.0, 0.0, 0.0), 10).unwrap(), Some(0));
+    assert!(matches!(
+        parsed.try_find_leaf((1.0, 0.0, 0.0), 5),
+        Err(BspError::TraversalTooDeep { limit: 5 })
+    ));
+    assert!(matches!(
+        parsed.try_raycast((1.0, 0.0, 0.0), (-1.0, 0.0, 0.0), 5),
+        Err(BspError::TraversalTooDeep { limit: 5 })
+    ));
+}
@@ -0,0 +1,329 @@
+use crate::lumps::lump_types::*;
+use crate::lumps::LumpParser::ParsedLumps;
+use crate::lumps::LumpType;
+
+use std::fmt;
+
+/// A reference from one lump into another that points outside of the target lump
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// The lump holding the reference
+    pub from: &'static str,
+    /// Index of the element holding the reference
+    pub from_index: usize,
+    /// The lump being referenced
+    pub to: &'static str,
+    /// The out of range index
+    pub to_index: i64,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}[{}] references {}[{}] which doesn't exist",
+            self.from, self.from_index, self.to, self.to_index
+        )
+    }
+}
+
+/// A lump stored in a version this crate doesn't read the layout of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionWarning {
+    pub lump: LumpType,
+    /// The version in the lump directory
+    pub found: i32,
+    /// The versions the crate can parse
+    pub expected: &'static [i32],
+}
+
+impl fmt::Display for VersionWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} lump is version {} but expected {:?}, it may be parsed wrong",
+            self.lump.name(),
+            self.found,
+            self.expected
+        )
+    }
+}
+
+/// Lump versions matching the layouts the parsers read, as written by Source 2013 era compilers
+const EXPECTED_VERSIONS: &[(LumpType, &[i32])] = &[
+    (LumpType::Plane, &[0]),
+    (LumpType::Texdata, &[0]),
+    (LumpType::Vertexes, &[0]),
+    (LumpType::Nodes, &[0]),
+    (LumpType::Texinfo, &[0]),
+    (LumpType::Faces, &[1]),
+    (LumpType::Lighting, &[0, 1]),
+    // Version 2 added the area to each occluder
+    (LumpType::Occlusion, &[2]),
+    // Version 0 includes the ambient lighting cube
+    (LumpType::Leafs, &[0, 1]),
+    (LumpType::Edges, &[0]),
+    (LumpType::Surfedges, &[0]),
+    (LumpType::Models, &[0]),
+    // Version 1 added the shadow cast offset to each light
+    (LumpType::Worldlights, &[0, 1]),
+    (LumpType::Leaffaces, &[0]),
+    (LumpType::Leafbrushes, &[0]),
+    (LumpType::Brushes, &[0]),
+    (LumpType::Brushsides, &[0]),
+    (LumpType::Areas, &[0]),
+    (LumpType::Areaportals, &[0]),
+    (LumpType::Dispinfo, &[0]),
+    (LumpType::Originalfaces, &[0]),
+    (LumpType::DispVerts, &[0]),
+    (LumpType::Cubemaps, &[0]),
+    (LumpType::Overlays, &[0]),
+    (LumpType::WorldlightsHDR, &[0, 1]),
+];
+
+/// Collects dangling references while walking the lumps
+struct Validator {
+    errors: Vec<ValidationError>,
+}
+
+impl Validator {
+    fn index(
+        &mut self,
+        from: &'static str,
+        from_index: usize,
+        to: &'static str,
+        index: i64,
+        len: usize,
+    ) {
+        if index < 0 || index as usize >= len {
+            self.errors.push(ValidationError {
+                from,
+                from_index,
+                to,
+                to_index: index,
+            });
+        }
+    }
+
+    /// Checks a first + count range, reporting the last index it reaches
+    fn range(
+        &mut self,
+        from: &'static str,
+        from_index: usize,
+        to: &'static str,
+        first: i64,
+        count: i64,
+        len: usize,
+    ) {
+        if count > 0 {
+            self.index(from, from_index, to, first, len);
+            self.index(from, from_index, to, first + count - 1, len);
+        }
+    }
+}
+
+impl ParsedLumps {
+    /// Lists the parsed lumps whose version doesn't match the layout the crate reads them with.
+    /// Parsing carries on regardless, so these point at data that may be garbage.
+    pub fn version_warnings(&self) -> Vec<VersionWarning> {
+        EXPECTED_VERSIONS
+            .iter()
+            .filter_map(|&(lump, expected)| {
+                let meta = self.lump_meta.get(lump as usize)?;
+                if meta.present && !expected.contains(&meta.version) {
+                    Some(VersionWarning {
+                        lump,
+                        found: meta.version,
+                        expected,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Checks that the indices each lump stores into other lumps are in range.
+    /// Renderers and tools can run this up front rather than panicking on a corrupt map later.
+    pub fn validate(&self) -> Vec<ValidationError> {
+        let mut v = Validator { errors: vec![] };
+
+        for (i, face) in self.faces.iter().enumerate() {
+            v.index(
+                "faces",
+                i,
+                "planes",
+                face.plane_num as i64,
+                self.planes.len(),
+            );
+            v.range(
+                "faces",
+                i,
+                "surfedges",
+                face.first_edge as i64,
+                face.num_edges as i64,
+                self.surfedges.len(),
+            );
+            if face.texinfo >= 0 {
+                v.index(
+                    "faces",
+                    i,
+                    "texinfo",
+                    face.texinfo as i64,
+                    self.texinfo.len(),
+                );
+            }
+            if face.displacement_info >= 0 {
+                v.index(
+                    "faces",
+                    i,
+                    "displacement_info",
+                    face.displacement_info as i64,
+                    self.displacement_info.len(),
+                );
+            }
+        }
+
+        for (i, surfedge) in self.surfedges.iter().enumerate() {
+            v.index(
+                "surfedges",
+                i,
+                "edges",
+                surfedge.unsigned_abs() as i64,
+                self.edges.len(),
+            );
+        }
+
+        for (i, edge) in self.edges.iter().enumerate() {
+            for &vertex in &edge.vertex_indicies {
+                v.index(
+                    "edges",
+                    i,
+                    "vertex_list",
+                    vertex as i64,
+                    self.vertex_list.len(),
+                );
+            }
+        }
+
+        for (i, texinfo) in self.texinfo.iter().enumerate() {
+            if texinfo.tex_data >= 0 {
+                v.index(
+                    "texinfo",
+                    i,
+                    "texdata",
+                    texinfo.tex_data as i64,
+                    self.texdata.len(),
+                );
+            }
+        }
+
+        if !self.texdata_string_table.is_empty() {
+            for (i, texdata) in self.texdata.iter().enumerate() {
+                v.index(
+                    "texdata",
+                    i,
+                    "texdata_string_table",
+                    texdata.texdata_string_table_index as i64,
+                    self.texdata_string_table.len(),
+                );
+            }
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            v.index(
+                "nodes",
+                i,
+                "planes",
+                node.plane_num as i64,
+                self.planes.len(),
+            );
+            // 16 bit face ranges wrap around on maps past the engine's face limit
+            v.range(
+                "nodes",
+                i,
+                "faces",
+                node.first_face as i64,
+                node.num_faces as i64,
+                self.faces.len(),
+            );
+            for side in 0..2 {
+                match node.child(side) {
+                    NodeChild::Node(child) => {
+                        v.index("nodes", i, "nodes", child as i64, self.nodes.len())
+                    }
+                    NodeChild::Leaf(child) => {
+                        v.index("nodes", i, "leaves", child as i64, self.leaves.len())
+                    }
+                }
+            }
+        }
+
+        for (i, leaf) in self.leaves.iter().enumerate() {
+            v.range(
+                "leaves",
+                i,
+                "leaf_faces",
+                leaf.first_leaf_face as i64,
+                leaf.num_leaf_faces as i64,
+                self.leaf_faces.len(),
+            );
+            v.range(
+                "leaves",
+                i,
+                "leaf_brushes",
+                leaf.first_leaf_brush as i64,
+                leaf.num_leaf_brushes as i64,
+                self.leaf_brushes.len(),
+            );
+        }
+
+        for (i, &face) in self.leaf_faces.iter().enumerate() {
+            v.index("leaf_faces", i, "faces", face as i64, self.faces.len());
+        }
+
+        for (i, &brush) in self.leaf_brushes.iter().enumerate() {
+            v.index(
+                "leaf_brushes",
+                i,
+                "brushes",
+                brush as i64,
+                self.brushes.len(),
+            );
+        }
+
+        for (i, model) in self.models.iter().enumerate() {
+            v.range(
+                "models",
+                i,
+                "faces",
+                model.first_face as i64,
+                model.num_faces as i64,
+                self.faces.len(),
+            );
+        }
+
+        for (i, brush) in self.brushes.iter().enumerate() {
+            v.range(
+                "brushes",
+                i,
+                "brushsides",
+                brush.first_side as i64,
+                brush.num_sides as i64,
+                self.brushsides.len(),
+            );
+        }
+
+        for (i, side) in self.brushsides.iter().enumerate() {
+            v.index(
+                "brushsides",
+                i,
+                "planes",
+                side.plane_num as i64,
+                self.planes.len(),
+            );
+        }
+
+        v.errors
+    }
+}
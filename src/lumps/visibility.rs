@@ -0,0 +1,226 @@
+use crate::lumps::lump_types::*;
+use crate::lumps::LumpParser::ParsedLumps;
+use crate::lumps::{clamped_slice, LumpReader};
+
+/// The potentially visible and audible sets of each visibility cluster
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Visibility {
+    pub num_clusters: i32,
+    /// Byte offsets of each cluster's PVS and PAS, from the start of the lump
+    pub offsets: Vec<[i32; 2]>,
+    /// The whole lump, so the offsets can index into it
+    pub data: Vec<u8>,
+}
+
+/// Index of the PVS in each pair of offsets, the PAS being the other one
+const PVS: usize = 0;
+const PAS: usize = 1;
+
+/// Reads bit n of a decompressed bit vector
+fn bit_set(bits: &[u8], n: usize) -> bool {
+    bits.get(n / 8)
+        .is_some_and(|byte| byte & (1 << (n % 8)) != 0)
+}
+
+impl BspParseable for Visibility {
+    fn from_reader(data: &mut LumpReader) -> Self {
+        let lump = data.get_data().to_vec();
+
+        let num_clusters = if data.remaining() >= 4 {
+            data.read_i32()
+        } else {
+            0
+        };
+
+        let mut offsets = vec![];
+        for _ in 0..num_clusters.max(0) {
+            if data.remaining() < 8 {
+                break;
+            }
+            offsets.push([data.read_i32(), data.read_i32()]);
+        }
+
+        data.skip_bytes(data.remaining());
+
+        Self {
+            num_clusters,
+            offsets,
+            data: lump,
+        }
+    }
+}
+
+impl Visibility {
+    /// Decompresses one of a cluster's bit vectors, with a bit per cluster
+    fn decompress(&self, cluster: usize, set: usize) -> Vec<u8> {
+        let row = (self.num_clusters.max(0) as usize).div_ceil(8);
+        let mut out = Vec::with_capacity(row);
+
+        let offset = match self.offsets.get(cluster) {
+            Some(offsets) if offsets[set] >= 0 => offsets[set] as usize,
+            _ => return vec![0; row],
+        };
+
+        let mut bytes = self.data.iter().skip(offset);
+        while out.len() < row {
+            match bytes.next() {
+                // A zero byte is followed by how many zero bytes it stands for
+                Some(0) => {
+                    let count = *bytes.next().unwrap_or(&0) as usize;
+                    out.resize((out.len() + count).min(row), 0);
+                    if count == 0 {
+                        break; // Corrupt, it would never finish
+                    }
+                }
+                Some(&byte) => out.push(byte),
+                None => break,
+            }
+        }
+
+        out.resize(row, 0);
+        out
+    }
+
+    /// Returns the cluster's PVS as a bit vector, bit n being set if cluster n may be visible
+    pub fn decompress_pvs(&self, cluster: usize) -> Vec<u8> {
+        self.decompress(cluster, PVS)
+    }
+
+    /// Returns the cluster's PAS as a bit vector, bit n being set if cluster n may be heard
+    pub fn decompress_pas(&self, cluster: usize) -> Vec<u8> {
+        self.decompress(cluster, PAS)
+    }
+
+    /// True if anything in cluster `to` may be visible from cluster `from`
+    pub fn cluster_visible(&self, from: usize, to: usize) -> bool {
+        bit_set(&self.decompress_pvs(from), to)
+    }
+}
+
+impl ParsedLumps {
+    /// Returns the faces that may be visible from a point, using the PVS of the leaf it's in.
+    /// Every face in the world's leaves is returned if the point is outside of any cluster or
+    /// the map has no visibility data.
+    pub fn visible_faces_from(&self, eye: Vector) -> Vec<&Face> {
+        let pvs = self
+            .find_leaf(eye)
+            .and_then(|leaf| self.leaves.get(leaf))
+            .filter(|leaf| leaf.cluster >= 0)
+            .and_then(|leaf| {
+                let visibility = self.visibility.as_ref()?;
+                Some(visibility.decompress_pvs(leaf.cluster as usize))
+            });
+
+        let mut visited = vec![false; self.faces.len()];
+        let mut out = vec![];
+
+        for leaf in &self.leaves {
+            let visible = match &pvs {
+                Some(pvs) => leaf.cluster >= 0 && bit_set(pvs, leaf.cluster as usize),
+                None => true,
+            };
+            if !visible {
+                continue;
+            }
+
+            let leaf_faces = clamped_slice(
+                &self.leaf_faces,
+                leaf.first_leaf_face as i64,
+                leaf.num_leaf_faces as i64,
+            );
+
+            for &face in leaf_faces {
+                // Leaves share faces, only add each once
+                if let Some(seen) = visited.get_mut(face as usize) {
+                    if !*seen {
+                        *seen = true;
+                        out.push(&self.faces[face as usize]);
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Pairs each leaf in a visibility cluster with the leaves that may be visible from it,
+    /// in index order. Leaves are grouped by cluster, with each cluster's PVS decompressed
+    /// once for all of its leaves. Nothing is returned for a map without visibility data.
+    pub fn pvs_pairs(&self) -> impl Iterator<Item = (usize, Vec<usize>)> + '_ {
+        let mut clusters: Vec<(i16, Vec<usize>)> = match self.visibility {
+            Some(_) => self.cluster_leaves().into_iter().collect(),
+            None => vec![],
+        };
+        clusters.sort_unstable();
+
+        let all_clusters = clusters.clone();
+        clusters.into_iter().flat_map(move |(cluster, leaves)| {
+            let pvs = self
+                .visibility
+                .as_ref()
+                .map(|visibility| visibility.decompress_pvs(cluster as usize))
+                .unwrap_or_default();
+
+            let mut visible: Vec<usize> = all_clusters
+                .iter()
+                .filter(|(other, _)| bit_set(&pvs, *other as usize))
+                .flat_map(|(_, leaves)| leaves.iter().copied())
+                .collect();
+            visible.sort_unstable();
+
+            leaves.into_iter().map(move |leaf| (leaf, visible.clone()))
+        })
+    }
+}
+
+#[test]
+fn test_visibility_rle() {
+    // 20 clusters, 3 bytes per row. Cluster 0 sees clusters 0 and 17, cluster 1 has no data.
+    let mut lump = vec![];
+    for value in [20i32, 20, 0, -1, -1] {
+        lump.extend_from_slice(&value.to_le_bytes());
+    }
+    lump.extend_from_slice(&[0x01, 0x00, 0x01, 0x02]);
+
+    let visibility = Visibility::from_reader(&mut LumpReader::new(&lump));
+
+    assert_eq!(visibility.offsets.len(), 2);
+    assert_eq!(visibility.decompress_pvs(0), [0x01, 0x00, 0x02]);
+    assert!(visibility.cluster_visible(0, 17));
+    assert!(!visibility.cluster_visible(0, 16));
+    assert!(!visibility.cluster_visible(1, 0));
+}
+
+#[test]
+fn test_pvs_pairs() {
+    // 3 clusters. Cluster 0 sees itself and 2, 1 sees itself and 2 sees everything.
+    let mut lump = vec![];
+    for value in [3i32, 28, -1, 29, -1, 30, -1] {
+        lump.extend_from_slice(&value.to_le_bytes());
+    }
+    lump.extend_from_slice(&[0b101, 0b010, 0b111]);
+
+    let leaf = |cluster| Leaf {
+        cluster,
+        ..crate::fixture::leaf(ContentsFlags::EMPTY)
+    };
+    let mut lumps = ParsedLumps {
+        leaves: vec![leaf(-1), leaf(0), leaf(1), leaf(2), leaf(0)],
+        ..Default::default()
+    };
+    assert_eq!(lumps.pvs_pairs().count(), 0);
+
+    lumps.visibility = Some(Visibility::from_reader(&mut LumpReader::new(&lump)));
+    let pairs: Vec<(usize, Vec<usize>)> = lumps.pvs_pairs().collect();
+
+    assert_eq!(
+        pairs,
+        [
+            (1, vec![1, 3, 4]),
+            (4, vec![1, 3, 4]),
+            (2, vec![2]),
+            (3, vec![1, 2, 3, 4]),
+        ]
+    );
+}
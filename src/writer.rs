@@ -0,0 +1,215 @@
+//! Lays lumps back out into a VBSP file
+
+use crate::error::BspError;
+use crate::lumps::{Endianness, LumpParser, LumpType};
+use crate::{BspParser, HEADER_SIZE, LUMP_COUNT, VBSP_MAGIC};
+
+use std::convert::TryInto;
+use std::io::{self, Write};
+
+/// Lumps start on 4 byte boundaries, the engine rejects maps that don't
+const LUMP_ALIGNMENT: usize = 4;
+
+/// A lump's bytes as they'll be stored in the file
+#[derive(Debug, Clone)]
+struct WriterLump {
+    version: i32,
+    /// The uncompressed size for compressed lumps, zero otherwise
+    ident: [u8; 4],
+    data: Vec<u8>,
+    /// Where the lump started in the file it was copied from. Game lump
+    /// directories hold file offsets, so they're moved by how far the lump moves.
+    source_offset: usize,
+}
+
+/// Writes a map from the bytes of each lump, in lump index order like VBSP.
+/// Each lump is padded to a 4 byte boundary, with its unpadded length in the directory.
+#[derive(Debug, Clone)]
+pub struct BspWriter {
+    version: i32,
+    map_revision: i32,
+    lumps: Vec<Option<WriterLump>>,
+}
+
+impl BspWriter {
+    /// Starts a map with no lumps
+    pub fn new(version: i32, map_revision: i32) -> Self {
+        BspWriter {
+            version,
+            map_revision,
+            lumps: vec![None; LUMP_COUNT],
+        }
+    }
+
+    /// Copies every lump of a map as it's stored, compressed lumps included
+    pub fn from_parser(parser: &BspParser) -> Result<Self, BspError> {
+        let header = parser.fetch_header()?;
+        // The lumps are copied as they're stored, which would mix byte orders
+        if parser.endianness() == Endianness::Big {
+            return Err(BspError::UnsupportedFormat {
+                detected: "Big-endian console VBSP",
+            });
+        }
+        if parser.data.len() < HEADER_SIZE {
+            return Err(BspError::TruncatedHeader {
+                needed: HEADER_SIZE,
+                got: parser.data.len(),
+            });
+        }
+
+        let mut writer = Self::new(header.version, header.map_revision);
+        for (i, lump) in parser.read_lump_info()?.iter().enumerate() {
+            if lump.fileofs == 0 || lump.filelen == 0 {
+                continue;
+            }
+
+            let data =
+                LumpParser::lump_bytes(lump, &parser.data).ok_or(BspError::LumpOutOfBounds {
+                    lump: i,
+                    offset: lump.fileofs,
+                    length: lump.filelen,
+                    file_len: parser.data.len(),
+                })?;
+
+            writer.lumps[i] = Some(WriterLump {
+                version: lump.version,
+                ident: lump.ident,
+                data: data.to_vec(),
+                source_offset: lump.fileofs as usize,
+            });
+        }
+
+        Ok(writer)
+    }
+
+    /// Sets the uncompressed contents of a lump. The offsets in a game lump's
+    /// directory are taken to be relative to the start of the game lump.
+    pub fn set_lump(&mut self, lump_type: LumpType, version: i32, data: Vec<u8>) {
+        self.lumps[lump_type as usize] = Some(WriterLump {
+            version,
+            ident: [0; 4],
+            data,
+            source_offset: 0,
+        });
+    }
+
+    /// Leaves a lump out of the map
+    pub fn remove_lump(&mut self, lump_type: LumpType) {
+        self.lumps[lump_type as usize] = None;
+    }
+
+    /// Lays out the whole file
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut directory = vec![];
+        let mut body = vec![];
+
+        for (i, lump) in self.lumps.iter().enumerate() {
+            let lump = match lump {
+                Some(lump) if !lump.data.is_empty() => lump,
+                _ => {
+                    directory.push((0, 0, 0, [0; 4]));
+                    continue;
+                }
+            };
+
+            while body.len() % LUMP_ALIGNMENT != 0 {
+                body.push(0);
+            }
+
+            let offset = HEADER_SIZE + body.len();
+            let start = body.len();
+            body.extend_from_slice(&lump.data);
+            if LumpType::from_index(i) == Some(LumpType::GameLump) {
+                let delta = offset as i64 - lump.source_offset as i64;
+                rebase_game_lumps(&mut body[start..], delta);
+            }
+
+            directory.push((offset, lump.data.len(), lump.version, lump.ident));
+        }
+
+        let mut out = VBSP_MAGIC.to_le_bytes().to_vec();
+        out.extend_from_slice(&self.version.to_le_bytes());
+        for (fileofs, filelen, version, ident) in directory {
+            out.extend_from_slice(&(fileofs as i32).to_le_bytes());
+            out.extend_from_slice(&(filelen as i32).to_le_bytes());
+            out.extend_from_slice(&version.to_le_bytes());
+            out.extend_from_slice(&ident);
+        }
+        out.extend_from_slice(&self.map_revision.to_le_bytes());
+        out.extend_from_slice(&body);
+
+        out
+    }
+
+    /// Writes the whole file
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(&self.to_bytes())
+    }
+}
+
+/// Moves the file offsets in a game lump directory, which follow the sub-lump count
+fn rebase_game_lumps(data: &mut [u8], delta: i64) {
+    let count = match data.get(0..4) {
+        Some(count) => i32::from_le_bytes(count.try_into().unwrap()).max(0) as usize,
+        None => return,
+    };
+
+    for i in 0..count {
+        // Each entry is the id, flags, version, fileofs and filelen
+        let at = 4 + i * 16 + 8;
+        let fileofs = match data.get_mut(at..at + 4) {
+            Some(fileofs) => fileofs,
+            None => break,
+        };
+
+        let old = i32::from_le_bytes((&*fileofs).try_into().unwrap());
+        if old > 0 {
+            fileofs.copy_from_slice(&((old as i64 + delta) as i32).to_le_bytes());
+        }
+    }
+}
+
+#[test]
+fn test_writer_round_trip() {
+    use crate::fixture::FixtureBuilder;
+
+    let parser = BspParser {
+        data: FixtureBuilder::new()
+            .entities("{\n\"classname\" \"worldspawn\"\n}\n")
+            .vertices(&[(0.0, 0.0, 0.0), (64.0, 0.0, 0.0), (64.0, 64.0, 0.0)])
+            .build()
+            .into(),
+    };
+
+    let mut writer = BspWriter::from_parser(&parser).unwrap();
+    // 6 bytes, so the lump after it needs padding
+    writer.set_lump(LumpType::Faceids, 0, vec![1, 0, 0, 0, 2, 0]);
+    let mut game_lump = 1i32.to_le_bytes().to_vec();
+    game_lump.extend_from_slice(b"prps");
+    game_lump.extend_from_slice(&[0; 4]); // flags and version
+    game_lump.extend_from_slice(&20i32.to_le_bytes());
+    game_lump.extend_from_slice(&4i32.to_le_bytes());
+    game_lump.extend_from_slice(b"data");
+    writer.set_lump(LumpType::GameLump, 0, game_lump);
+
+    let written = BspParser {
+        data: writer.to_bytes().into(),
+    };
+    let lumps = written.read_lump_info().unwrap();
+
+    for lump in &lumps {
+        assert_eq!(lump.fileofs % 4, 0);
+    }
+    assert_eq!(lumps[LumpType::Faceids as usize].filelen, 6);
+    for lump_type in [LumpType::Entities, LumpType::Vertexes] {
+        assert_eq!(
+            written.lump_bytes(lump_type).unwrap(),
+            parser.lump_bytes(lump_type).unwrap()
+        );
+    }
+
+    let parsed = LumpParser::parse_lump_data(lumps, &written.data);
+    assert_eq!(parsed.entities.len(), 1);
+    assert_eq!(parsed.vertex_list.len(), 3);
+    assert_eq!(parsed.game_lumps[0].data, b"data");
+}